@@ -1,123 +1,512 @@
-use anyhow::Result;
-use clap::{command, value_parser, Arg, ArgAction};
+use anyhow::{anyhow, Context, Result};
+use clap::{command, parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches, Command};
 use git2::Repository;
-use std::{env, fmt, net::Ipv4Addr, path::PathBuf};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
 use walkdir::{DirEntry, WalkDir};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct Config {
+    /// site title of the pages and feeds
+    pub title: String,
     pub bind: Ipv4Addr,
     pub port: u16,
     pub path: PathBuf,
     pub repo: Vec<Repo>,
     pub mode: Mode,
+    /// directories below the rogit path searched for repositories
+    pub max_depth: usize,
+    /// paths relative to the rogit path not searched
+    pub ignore: GlobSet,
+    /// repositories updated at the same time
+    pub jobs: usize,
+    /// compact rogit.db after garbage collection
+    pub vacuum: bool,
+    /// sqlite `synchronous` of the update, durability against speed
+    pub synchronous: String,
+    /// update repositories in the background of server mode
+    pub interval: Option<Duration>,
+    /// update a repository when its refs change
+    pub watch: bool,
+    /// fetch the upstream of each repository before updating it
+    pub fetch: bool,
+    pub fetch_timeout: Duration,
+    /// bearer token of the update hook, disabled without
+    pub token: Option<String>,
+    /// only update this repository
+    pub target: Option<String>,
+    pub limits: Limits,
+    /// `[repo.NAME]` tables of the config file
+    pub overrides: HashMap<String, RepoMeta>,
 }
 
-#[derive(Debug)]
+/// Sizes of pages and requests the server allows
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// commits and tags on the summary page
+    pub summary: u32,
+    /// commits per log page
+    pub log: u32,
+    /// entries of atom feeds
+    pub feed: u32,
+    /// files matched by code search
+    pub search: u32,
+    /// bytes of patch on the commit page
+    pub patch: usize,
+    /// bytes of a git-upload-pack request, after gzip decoding
+    pub upload: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            summary: 10,
+            log: 50,
+            feed: 50,
+            search: 100,
+            patch: 512 << 10,
+            upload: 64 << 20,
+        }
+    }
+}
+
+/// Settings of one repository in the config file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RepoMeta {
+    /// shown instead of the name in the index
+    pub display_name: Option<String>,
+    /// replaces the `description` file
+    pub description: Option<String>,
+    /// not listed in the index, still served by name
+    pub hidden: bool,
+    /// branch shown instead of HEAD
+    pub default_branch: Option<String>,
+    pub category: Option<String>,
+}
+
+/// `rogit.toml` in the rogit path or `--config`, flags take precedence
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct File {
+    /// only read with `--config`
+    path: Option<PathBuf>,
+    title: Option<String>,
+    bind: Option<Ipv4Addr>,
+    port: Option<u16>,
+    jobs: Option<usize>,
+    synchronous: Option<String>,
+    interval: Option<u64>,
+    watch: bool,
+    fetch: bool,
+    fetch_timeout: Option<u64>,
+    token: Option<String>,
+    max_depth: Option<u32>,
+    ignore: Option<Vec<String>>,
+    limits: Limits,
+    repo: HashMap<String, RepoMeta>,
+}
+
+impl File {
+    fn read(path: &Path) -> Result<Self> {
+        let bad = || format!("bad config {}", path.display());
+        let text = fs::read_to_string(path).with_context(bad)?;
+        let file: File = toml::from_str(&text).with_context(bad)?;
+        if let Some(s) = &file.synchronous {
+            if !SYNCHRONOUS.contains(&s.as_str()) {
+                let e = anyhow!("synchronous must be one of {}", SYNCHRONOUS.join(", "));
+                return Err(e).with_context(bad);
+            }
+        }
+        let l = &file.limits;
+        let zero = [l.summary, l.log, l.feed, l.search].contains(&0);
+        let zero = zero || l.patch == 0 || l.upload == 0 || file.max_depth == Some(0);
+        let zero = zero || file.port == Some(0) || file.interval == Some(0);
+        if zero || file.fetch_timeout == Some(0) {
+            let e = anyhow!("port, interval, fetch-timeout, max-depth and limits must not be 0");
+            return Err(e).with_context(bad);
+        }
+        Ok(file)
+    }
+}
+
+const SYNCHRONOUS: [&str; 4] = ["off", "normal", "full", "extra"];
+
+fn globs(patterns: &[String]) -> Result<GlobSet> {
+    let mut set = GlobSetBuilder::new();
+    for p in patterns {
+        set.add(Glob::new(p)?);
+    }
+    Ok(set.build()?)
+}
+
+/// Value from the command line or environment, else from the file, else
+/// the default of the flag
+fn pick<T: Clone + Send + Sync + 'static>(m: &ArgMatches, id: &str, file: Option<T>) -> T {
+    match (m.value_source(id), file) {
+        (Some(ValueSource::DefaultValue), Some(value)) => value,
+        _ => m.get_one::<T>(id).unwrap().clone(),
+    }
+}
+
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct Repo {
     pub name: String,
     pub path: PathBuf,
+    /// `rogit.interval` of the git config, overrides `Config::interval`
+    pub interval: Option<Duration>,
+    /// `rogit.upstream` of the git config, or else `remote.origin.url`
+    pub upstream: Option<String>,
+    pub meta: RepoMeta,
 }
 
-#[derive(Debug)]
+/// Flags and arguments of the command line
+fn command() -> Command {
+    command!()
+        .arg(
+            Arg::new("bind")
+                .long("bind")
+                .value_name("BIND")
+                .default_value("127.0.0.1")
+                .value_parser(value_parser!(Ipv4Addr))
+                .help("bind to interface"),
+        )
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .value_name("PORT")
+                .default_value("8080")
+                .value_parser(value_parser!(u16).range(1..))
+                .help("port to listen on"),
+        )
+        .arg(
+            Arg::new("update")
+                .long("update")
+                .conflicts_with_all(["bind", "port"])
+                .action(ArgAction::SetTrue)
+                .help("update git repositories to rogit.db"),
+        )
+        .arg(
+            Arg::new("migrate")
+                .long("migrate")
+                .conflicts_with_all(["bind", "port", "update", "check"])
+                .action(ArgAction::SetTrue)
+                .help("upgrade the schema of rogit.db"),
+        )
+        .arg(
+            Arg::new("check")
+                .long("check")
+                .conflicts_with_all(["bind", "port", "update"])
+                .action(ArgAction::SetTrue)
+                .help("check the schema version of rogit.db"),
+        )
+        .arg(
+            Arg::new("jobs")
+                .long("jobs")
+                .short('j')
+                .value_name("JOBS")
+                .value_parser(value_parser!(usize))
+                .help("number of repositories to update in parallel"),
+        )
+        .arg(
+            Arg::new("vacuum")
+                .long("vacuum")
+                .action(ArgAction::SetTrue)
+                .help("vacuum rogit.db after update"),
+        )
+        .arg(
+            Arg::new("synchronous")
+                .long("synchronous")
+                .value_name("LEVEL")
+                .default_value("normal")
+                .value_parser(SYNCHRONOUS)
+                .help("sqlite synchronous level, `normal` may lose the last update on power loss"),
+        )
+        .arg(
+            Arg::new("interval")
+                .long("interval")
+                .value_name("SECONDS")
+                .conflicts_with_all(["update", "migrate", "check"])
+                .value_parser(value_parser!(u64).range(1..))
+                .help("update repositories while serving, SIGHUP updates at once"),
+        )
+        .arg(
+            Arg::new("fetch")
+                .long("fetch")
+                .conflicts_with_all(["migrate", "check"])
+                .action(ArgAction::SetTrue)
+                .help("fetch upstream of repositories before update"),
+        )
+        .arg(
+            Arg::new("fetch-timeout")
+                .long("fetch-timeout")
+                .value_name("SECONDS")
+                .default_value("300")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("abort a fetch taking longer"),
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .conflicts_with_all(["update", "migrate", "check"])
+                .action(ArgAction::SetTrue)
+                .help("update repositories while serving when their refs change"),
+        )
+        .arg(
+            Arg::new("token")
+                .long("token")
+                .value_name("TOKEN")
+                .env("ROGIT_TOKEN")
+                .hide_env_values(true)
+                .conflicts_with_all(["update", "migrate", "check"])
+                .help("enable POST /:repo/-/update with this bearer token"),
+        )
+        .arg(
+            Arg::new("max-depth")
+                .long("max-depth")
+                .value_name("DEPTH")
+                .default_value("3")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("search repositories in subdirectories down to DEPTH"),
+        )
+        .arg(
+            Arg::new("ignore")
+                .long("ignore")
+                .value_name("GLOB")
+                .action(ArgAction::Append)
+                .help("skip paths relative to ROGIT-PATH matching GLOB, repeatable"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("read settings from FILE instead of ROGIT-PATH/rogit.toml"),
+        )
+        .arg(
+            Arg::new("path")
+                .value_name("ROGIT-PATH")
+                .required(false)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .subcommand(
+            Command::new("update")
+                .about("update one repository to rogit.db")
+                .arg(Arg::new("repo").value_name("REPO").required(true)),
+        )
+}
+
+#[derive(Debug, Clone)]
 pub enum Mode {
     Update,
     Server,
+    Migrate,
+    Check,
 }
 
 #[allow(dead_code)]
 impl Config {
     pub fn new() -> Result<Self> {
-        let matches = command!()
-            .arg(
-                Arg::new("bind")
-                    .long("bind")
-                    .value_name("BIND")
-                    .default_value("127.0.0.1")
-                    .value_parser(value_parser!(Ipv4Addr))
-                    .help("bind to interface"),
-            )
-            .arg(
-                Arg::new("port")
-                    .long("port")
-                    .value_name("PORT")
-                    .default_value("8080")
-                    .value_parser(value_parser!(u16).range(1..))
-                    .help("port to listen on"),
-            )
-            .arg(
-                Arg::new("update")
-                    .long("update")
-                    .conflicts_with_all(["bind", "port"])
-                    .action(ArgAction::SetTrue)
-                    .help("update git repositories to rogit.db"),
-            )
-            .arg(
-                &Arg::new("path")
-                    .value_name("ROGIT-PATH")
-                    .required(false)
-                    .value_parser(value_parser!(PathBuf)),
-            )
-            .get_matches();
-        let current_dir = env::current_dir().unwrap();
-        let path = matches.get_one::<PathBuf>("path").unwrap_or(&current_dir);
-
-        let mode = if matches.get_flag("update") {
-            Mode::Update
-        } else {
+        let c = Self::from_matches(&command().get_matches())?;
+        if matches!(c.mode, Mode::Server) {
             git2::opts::enable_caching(false);
-            Mode::Server
-        };
+        }
         git2::opts::strict_hash_verification(false);
         unsafe {
             git2::opts::set_mwindow_file_limit(64)?;
         }
-        let bind = matches.get_one::<Ipv4Addr>("bind").unwrap();
-        let port = matches.get_one::<u16>("port").unwrap();
+        Ok(c)
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Result<Self> {
+        let config = matches.get_one::<PathBuf>("config");
+        let file = match config {
+            Some(config) => File::read(config)?,
+            None => File::default(),
+        };
+        let path = match matches.get_one::<PathBuf>("path").or(file.path.as_ref()) {
+            Some(path) => path.clone(),
+            None => env::current_dir()?,
+        };
+        let file = match path.join("rogit.toml") {
+            default if config.is_none() && default.is_file() => File::read(&default)?,
+            _ => file,
+        };
+
+        let target = matches
+            .subcommand_matches("update")
+            .map(|m| m.get_one::<String>("repo").unwrap().clone());
+        let mode = if matches.get_flag("update") || target.is_some() {
+            Mode::Update
+        } else if matches.get_flag("migrate") {
+            Mode::Migrate
+        } else if matches.get_flag("check") {
+            Mode::Check
+        } else {
+            Mode::Server
+        };
+        // clap cannot require a subcommand, `update <REPO>` counts too
+        let vacuum = matches.get_flag("vacuum");
+        if vacuum && !matches!(mode, Mode::Update) {
+            return Err(anyhow!("--vacuum needs --update or the update subcommand"));
+        }
+        let jobs = match matches.get_one::<usize>("jobs").copied().or(file.jobs) {
+            Some(jobs) => jobs,
+            None => thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        // interval, watch and token only start the scheduler in server mode
+        let server = matches!(mode, Mode::Server);
+        let interval = matches
+            .get_one::<u64>("interval")
+            .copied()
+            .or(file.interval.filter(|_| server));
+
+        let ignore = match matches.get_many::<String>("ignore") {
+            Some(patterns) => patterns.cloned().collect(),
+            None => file.ignore.unwrap_or_default(),
+        };
 
         let repo: Vec<Repo> = Vec::new();
 
         Ok(Self {
-            bind: bind.clone(),
-            port: port.clone(),
-            path: path.clone(),
+            title: file.title.unwrap_or_else(|| "rogit".to_owned()),
+            bind: pick(matches, "bind", file.bind),
+            port: pick(matches, "port", file.port),
+            path,
             repo,
             mode,
+            max_depth: pick::<u32>(matches, "max-depth", file.max_depth) as usize,
+            ignore: globs(&ignore).context("bad ignore pattern")?,
+            jobs: jobs.max(1),
+            vacuum,
+            synchronous: pick(matches, "synchronous", file.synchronous),
+            interval: interval.map(Duration::from_secs),
+            watch: matches.get_flag("watch") || file.watch && server,
+            fetch: matches.get_flag("fetch") || file.fetch,
+            fetch_timeout: Duration::from_secs(pick(matches, "fetch-timeout", file.fetch_timeout)),
+            token: matches
+                .get_one::<String>("token")
+                .cloned()
+                .or(file.token.filter(|_| server)),
+            target,
+            limits: file.limits,
+            overrides: file.repo,
         })
     }
 
+    /// Find repositories below the rogit path, bare ones and work trees,
+    /// real or linked. Names are the relative paths, repositories are not
+    /// searched for nested ones.
     pub fn scan(&mut self) {
         let mut repos: Vec<Repo> = Vec::new();
-        for entry in WalkDir::new(&self.path)
+        let mut walk = WalkDir::new(&self.path)
             .min_depth(1)
-            .max_depth(1)
+            .max_depth(self.max_depth)
+            .sort_by_file_name()
             .into_iter()
-            .filter_entry(|e| Self::is_repo(e))
-            .filter_map(|r| r.inspect_err(|e| eprintln!("walkdir error: {}", e)).ok())
-        {
-            let link = entry.into_path();
-            let name = link.file_name().unwrap().to_string_lossy().to_string();
-            let path = std::fs::read_link(&link).unwrap();
+            .filter_entry(|e| !self.is_ignored(e));
+        while let Some(entry) = walk.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("[scan] walkdir error: {}", e);
+                    continue;
+                }
+            };
+            let Some(path) = Self::git_dir(&entry) else {
+                continue;
+            };
+            if entry.file_type().is_dir() {
+                walk.skip_current_dir();
+            }
+            let name = entry
+                .path()
+                .strip_prefix(&self.path)
+                .unwrap()
+                .iter()
+                .map(|c| c.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
             println!("[scan] sync: {}\t-> {}", name, path.display());
-            repos.push(Repo { name, path });
+            let (interval, upstream) = Self::repo_config(&path);
+            let meta = self.overrides.get(&name).cloned().unwrap_or_default();
+            repos.push(Repo {
+                name,
+                path,
+                interval,
+                upstream,
+                meta,
+            });
         }
         if repos.is_empty() {
             println!("[scan] no git repository found!");
-        } else {
-            self.repo = repos
+        }
+        self.repo = repos;
+    }
+
+    /// Seconds of `rogit.interval`, 0 updates only on demand, and the
+    /// upstream url to fetch
+    fn repo_config(path: &Path) -> (Option<Duration>, Option<String>) {
+        let Some(config) = Repository::open_bare(path).and_then(|r| r.config()).ok() else {
+            return (None, None);
         };
+        let interval = config
+            .get_i64("rogit.interval")
+            .ok()
+            .and_then(|secs| u64::try_from(secs).ok())
+            .map(Duration::from_secs);
+        let upstream = config
+            .get_string("rogit.upstream")
+            .or_else(|_| config.get_string("remote.origin.url"))
+            .ok();
+        (interval, upstream)
     }
 
-    fn is_repo(entry: &DirEntry) -> bool {
-        if !entry.path_is_symlink() {
+    /// Hidden files and `--ignore` matches, with everything below them.
+    /// `-` separates names from actions in urls, never part of a name.
+    fn is_ignored(&self, entry: &DirEntry) -> bool {
+        if entry.depth() == 0 {
             return false;
         }
-        // TODO skip hidden file
-        Repository::open_bare(entry.path())
-            .inspect_err(|e| eprintln!("[scan] skip: {}", e.message()))
-            .is_ok()
+        let file_name = entry.file_name().to_string_lossy();
+        if file_name.starts_with('.') || file_name == "-" {
+            return true;
+        }
+        let relative = entry.path().strip_prefix(&self.path).unwrap();
+        self.ignore.is_match(relative)
+    }
+
+    /// Git directory of the entry, itself if bare or its `.git`. Links
+    /// are resolved but never searched further.
+    fn git_dir(entry: &DirEntry) -> Option<PathBuf> {
+        let path = if entry.path_is_symlink() {
+            let target = fs::read_link(entry.path()).ok()?;
+            entry.path().parent()?.join(target)
+        } else {
+            entry.path().to_owned()
+        };
+        if !path.is_dir() {
+            return None;
+        }
+        let found = [path.clone(), path.join(".git")]
+            .into_iter()
+            .find(|p| Repository::open_bare(p).is_ok());
+        if found.is_none() && entry.path_is_symlink() {
+            let path = entry.path().display();
+            eprintln!("[scan] skip: {} is not a git repository", path);
+        }
+        found
     }
 }
 
@@ -128,8 +517,105 @@ impl fmt::Display for Config {
             writeln!(f, "* {}\t->{}", r.name, r.path.display())?;
         }
         match self.mode {
-            Mode::Update => write!(f, "mode: update"),
+            Mode::Update => match &self.target {
+                Some(name) => write!(f, "mode: update {}", name),
+                None => write!(f, "mode: update, {} jobs", self.jobs),
+            },
             Mode::Server => write!(f, "mode: server {}:{}", self.bind, self.port),
+            Mode::Migrate => write!(f, "mode: migrate"),
+            Mode::Check => write!(f, "mode: check"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(args: &[&str]) -> ArgMatches {
+        let args = std::iter::once("rogit").chain(args.iter().copied());
+        command().try_get_matches_from(args).unwrap()
+    }
+
+    /// Names found below a tempdir holding `bare` and `work` repositories
+    fn scan(bare: &[&str], work: &[&str], args: &[&str]) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        for name in bare {
+            Repository::init_bare(dir.path().join(name)).unwrap();
+        }
+        for name in work {
+            Repository::init(dir.path().join(name)).unwrap();
         }
+        let root = dir.path().to_str().unwrap();
+        let args: Vec<&str> = std::iter::once(root).chain(args.iter().copied()).collect();
+        let mut c = Config::from_matches(&matches(&args)).unwrap();
+        c.scan();
+        c.repo.into_iter().map(|r| r.name).collect()
+    }
+
+    #[test]
+    fn scan_depth() {
+        let bare = ["a.git", "group/b.git", "group/sub/c.git", "x/y/z/d.git"];
+        let names = scan(&bare, &["w"], &[]);
+        assert_eq!(names, ["a.git", "group/b.git", "group/sub/c.git", "w"]);
+        let names = scan(&bare, &["w"], &["--max-depth", "1"]);
+        assert_eq!(names, ["a.git", "w"]);
+    }
+
+    #[test]
+    fn scan_ignore() {
+        let bare = [
+            "a.git",
+            ".hidden/b.git",
+            "-/c.git",
+            "skip/d.git",
+            "keep/e.git",
+        ];
+        let names = scan(&bare, &[], &["--ignore", "skip"]);
+        assert_eq!(names, ["a.git", "keep/e.git"]);
+        let names = scan(&bare, &[], &["--ignore", "*/e.git", "--ignore", "a.*"]);
+        assert_eq!(names, ["skip/d.git"]);
+    }
+
+    #[test]
+    fn scan_stops_at_repositories() {
+        let names = scan(&["a.git", "a.git/nested.git"], &["w", "w/inner"], &[]);
+        assert_eq!(names, ["a.git", "w"]);
+    }
+
+    #[test]
+    fn pick_command_line_over_file() {
+        let m = matches(&["--port", "9001", "--max-depth", "1"]);
+        assert_eq!(pick::<u16>(&m, "port", Some(9000)), 9001);
+        assert_eq!(pick::<u32>(&m, "max-depth", Some(5)), 1);
+    }
+
+    #[test]
+    fn pick_file_over_default() {
+        let m = matches(&[]);
+        assert_eq!(pick::<u16>(&m, "port", Some(9000)), 9000);
+        let synchronous = pick(&m, "synchronous", Some("full".to_owned()));
+        assert_eq!(synchronous, "full");
+    }
+
+    #[test]
+    fn pick_default() {
+        let m = matches(&[]);
+        assert_eq!(pick::<u16>(&m, "port", None), 8080);
+        assert_eq!(pick::<u64>(&m, "fetch-timeout", None), 300);
+        assert_eq!(pick::<String>(&m, "synchronous", None), "normal");
+    }
+
+    #[test]
+    fn vacuum_in_update_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let c = Config::from_matches(&matches(&[path, "--update", "--vacuum"])).unwrap();
+        assert!(c.vacuum);
+        let c = Config::from_matches(&matches(&[path, "--vacuum", "update", "a.git"])).unwrap();
+        assert!(c.vacuum);
+        assert_eq!(c.target.as_deref(), Some("a.git"));
+        assert!(Config::from_matches(&matches(&[path, "--vacuum"])).is_err());
+        assert!(Config::from_matches(&matches(&[path, "--migrate", "--vacuum"])).is_err());
     }
 }
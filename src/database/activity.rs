@@ -0,0 +1,91 @@
+use anyhow::Result;
+use indoc::indoc;
+use rusqlite::{named_params, Connection, Transaction};
+use serde::Deserialize;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+/// Rebuild the rollup of the repository from author dates, kept in
+/// the author's local time so the punch card shows working hours.
+pub fn refresh(tx: &Transaction, repo_id: i64) -> Result<()> {
+    let mut stmt = tx.prepare_cached(indoc! { r#"
+        DELETE FROM "activities" WHERE "repo" = :repo
+        "# })?;
+    stmt.execute(named_params! {":repo": repo_id})?;
+
+    let mut stmt = tx.prepare_cached(indoc! { r#"
+        INSERT INTO "activities" (repo, author, day, hour, commits)
+        SELECT c.repo, m.author, substr(m.date, 1, 10),
+            CAST(substr(m.date, 12, 2) AS INTEGER), COUNT(*)
+        FROM "commits" c
+        JOIN "messages" m ON m.id = c.msg_id
+        WHERE c.repo = :repo AND m.date IS NOT NULL
+        GROUP BY 1, 2, 3, 4
+        "# })?;
+    stmt.execute(named_params! {":repo": repo_id})?;
+    Ok(())
+}
+
+/// Commit count per bucket, oldest first. Weeks start on Monday and are
+/// labeled by that day.
+pub fn series(
+    conn: &Connection,
+    repo_id: Option<i64>,
+    author_id: Option<i64>,
+    by: Bucket,
+) -> Result<Vec<(String, i64)>> {
+    let bucket = match by {
+        Bucket::Day => "day",
+        Bucket::Week => "date(day, 'weekday 0', '-6 days')",
+        Bucket::Month => "substr(day, 1, 7)",
+    };
+    let mut stmt = conn.prepare_cached(&format!(
+        indoc! { r#"
+        SELECT {} AS bucket, SUM(a.commits) FROM "activities" a
+        JOIN "repositories" r ON r.id = a.repo
+        WHERE (:repo IS NULL AND NOT r.hidden OR a.repo = :repo)
+            AND (:author IS NULL OR a.author = :author)
+        GROUP BY bucket
+        ORDER BY bucket
+        "# },
+        bucket
+    ))?;
+    let rows = stmt
+        .query_map(
+            named_params! {":repo": repo_id, ":author": author_id},
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
+
+/// Commit count per (weekday, hour), weekday 0 is Sunday
+pub fn punchcard(
+    conn: &Connection,
+    repo_id: Option<i64>,
+    author_id: Option<i64>,
+) -> Result<Vec<(i64, i64, i64)>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT CAST(strftime('%w', a.day) AS INTEGER) AS weekday, a.hour, SUM(a.commits)
+        FROM "activities" a
+        JOIN "repositories" r ON r.id = a.repo
+        WHERE (:repo IS NULL AND NOT r.hidden OR a.repo = :repo)
+            AND (:author IS NULL OR a.author = :author)
+        GROUP BY weekday, hour
+        ORDER BY weekday, hour
+        "# })?;
+    let rows = stmt
+        .query_map(
+            named_params! {":repo": repo_id, ":author": author_id},
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+        )?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
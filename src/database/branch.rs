@@ -1,6 +1,78 @@
 use anyhow::Result;
-use core::str;
-use git2::Oid;
-use log::debug;
-use rusqlite::{named_params, OptionalExtension, Transaction};
+use indoc::indoc;
+use rusqlite::{named_params, Connection, OptionalExtension, Transaction};
+use serde::Serialize;
 
+#[derive(Serialize)]
+pub struct Branch {
+    pub name: String,
+    pub hash: String,
+    pub date: String,
+    pub msg: String,
+    /// author of the tip commit
+    pub author: String,
+    pub mail: String,
+}
+
+/// Returns false if the branch already pointed to `commit_id`, which is
+/// only marked as alive
+pub fn upsert(tx: &Transaction, name: &str, commit_id: i64, repo_id: i64) -> Result<bool> {
+    let params = named_params! {":name": name, ":hash": commit_id, ":repo": repo_id};
+    let mut stmt = tx.prepare_cached(indoc! { r#"
+        UPDATE "branches" SET fake = 0
+        WHERE repo = :repo AND name = :name AND hash = :hash
+        "# })?;
+    if stmt.execute(params)? > 0 {
+        return Ok(false);
+    }
+    let mut stmt = tx.prepare_cached(indoc! { r#"
+        INSERT INTO "branches" (name, hash, repo, fake)
+        VALUES (:name, :hash, :repo, 0)
+        ON CONFLICT(repo, name)
+        DO UPDATE SET
+            hash = EXCLUDED.hash,
+            fake = 0;
+        "# })?;
+    stmt.execute(params)?;
+    Ok(true)
+}
+
+/// Hash of the tip commit of branch `name`
+pub fn tip(conn: &Connection, repo_id: i64, name: &str) -> Result<Option<String>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT c.hash FROM "branches" b
+        JOIN "commits" c ON c.id = b.hash
+        WHERE b.repo = :repo AND b.name = :name
+        "# })?;
+    let params = named_params! {":repo": repo_id, ":name": name};
+    let hash = stmt.query_row(params, |r| r.get(0)).optional()?;
+    Ok(hash)
+}
+
+/// Branches with their tip commit, latest updated first
+pub fn list(conn: &Connection, repo_id: i64) -> Result<Vec<Branch>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT b.name, c.hash,
+            strftime('%Y-%m-%dT%H:%M:%SZ', unixepoch(c.date), 'unixepoch'), m.msg,
+            p.name, p.mail
+        FROM "branches" b
+        JOIN "commits" c ON c.id = b.hash
+        JOIN "messages" m ON m.id = c.msg_id
+        JOIN "contributors" p ON p.id = m.author
+        WHERE b.repo = :repo
+        ORDER BY unixepoch(c.date) DESC, b.name
+        "# })?;
+    let rows = stmt
+        .query_map(named_params! {":repo": repo_id}, |r| {
+            Ok(Branch {
+                name: r.get(0)?,
+                hash: r.get(1)?,
+                date: r.get(2)?,
+                msg: r.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                author: r.get(4)?,
+                mail: r.get(5)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
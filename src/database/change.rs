@@ -1,53 +1,92 @@
 use anyhow::{Context, Result};
 use core::str;
-use git2::{Delta, Diff, DiffDelta, DiffFile};
+use git2::{Delta, Diff, DiffDelta, DiffFile, Patch};
 use indoc::indoc;
-use rusqlite::{named_params, Transaction};
+use rusqlite::{named_params, Connection, Transaction};
+use serde::Serialize;
 
-pub fn insert(tx: &Transaction, relation_id: i64, diff: &Diff) -> Result<()> {
+/// Changed file of a diff, owned so a worker can hand it to the writer
+pub struct File {
+    pub path: String,
+    pub mode: i64,
+    /// lines added and removed, `None` if the diff was not computed
+    pub lines: Option<(usize, usize)>,
+}
+
+/// Changed files of the diff, with line counts if `lines` and the diff
+/// was made with text content
+pub fn files(diff: &Diff, lines: bool) -> Result<Vec<File>> {
+    let mut files = Vec::with_capacity(diff.deltas().len());
+    for (i, delta) in diff.deltas().enumerate() {
+        let lines = if lines {
+            Patch::from_diff(diff, i)?
+                .map(|p| p.line_stats())
+                .transpose()?
+        } else {
+            None
+        };
+        files.push(File {
+            path: get_path(&delta.new_file()).unwrap_or_default(),
+            mode: get_mode(&delta),
+            lines: lines.map(|(_, added, removed)| (added, removed)),
+        });
+    }
+    Ok(files)
+}
+
+pub fn insert(tx: &Transaction, relation_id: i64, files: &[File], repo_id: i64) -> Result<()> {
     let mut stmt = tx.prepare_cached(indoc! { r#"
-    INSERT INTO "changes" (diff, mode, file)
-    VALUES (:diff, :mode, :file)
+    INSERT INTO "changes" (diff, mode, file, added, removed)
+    VALUES (:diff, :mode, :file, :added, :removed)
     "# })?;
 
-    for delta in diff.deltas() {
-        let path = get_path(&delta.new_file()).unwrap_or_default();
-        let file_id =
-            get_file(tx, &path).with_context(|| format!("Failed to query id: {}", path))?;
-        let mode = get_mode(&delta);
+    for f in files {
+        let file_id = get_file(tx, &f.path, repo_id)
+            .with_context(|| format!("Failed to query id: {}", f.path))?;
 
         stmt.insert(named_params! {
             ":diff": relation_id,
-            ":mode": mode,
+            ":mode": f.mode,
             ":file": file_id,
+            ":added": f.lines.map(|(added, _)| added),
+            ":removed": f.lines.map(|(_, removed)| removed),
         })?;
     }
     Ok(())
 }
 
-fn get_file(tx: &Transaction, path: &str) -> Result<i64> {
+fn get_file(tx: &Transaction, path: &str, repo_id: i64) -> Result<i64> {
     let mut stmt = tx.prepare_cached(indoc! { r#"
-    INSERT OR IGNORE INTO "editfiles" (path)
-    VALUES (:path)
+    INSERT OR IGNORE INTO "editfiles" (repo, path)
+    VALUES (:repo, :path)
     "# })?;
-    stmt.execute(named_params! {":path": path})?;
+    stmt.execute(named_params! {":repo": repo_id, ":path": path})?;
 
     let mut stmt = tx.prepare_cached(indoc! { r#"
     SELECT "id" FROM "editfiles"
-    WHERE path = :path
+    WHERE repo = :repo AND path = :path
     LIMIT 1
     "# })?;
-    let id = stmt.query_row(named_params! {":path": path}, |r| r.get::<_, i64>(0))?;
+    let id = stmt.query_row(named_params! {":repo": repo_id, ":path": path}, |r| {
+        r.get::<_, i64>(0)
+    })?;
     Ok(id)
 }
 
+/// Bounds of the paths below directory `prefix`, a range on (repo, path)
+/// lets SQLite use the unique index of editfiles
+pub fn prefix_range(prefix: &str) -> (String, String) {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        return (String::new(), String::from(char::MAX));
+    }
+    // '0' is the byte right after '/'
+    (format!("{prefix}/"), format!("{prefix}0"))
+}
+
 fn get_path(file: &DiffFile) -> Option<String> {
-    let Some(path) = file.path() else {
-        return None;
-    };
-    let Some(path_str) = path.to_str() else {
-        return None;
-    };
+    let path = file.path()?;
+    let path_str = path.to_str()?;
     Some(path_str.to_owned())
 }
 
@@ -66,3 +105,48 @@ fn get_mode(delta: &DiffDelta) -> i64 {
         Delta::Conflicted => 10,
     }
 }
+
+#[derive(Serialize)]
+pub struct Change {
+    pub parent: String,
+    pub mode: char,
+    pub path: String,
+}
+
+/// Changed files of the commit against each parent
+pub fn list(conn: &Connection, hash: &str, repo_id: i64) -> Result<Vec<Change>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+    SELECT p.hash, ch.mode, e.path FROM "commits" c
+    JOIN "relations" r ON r.child = c.id
+    JOIN "commits" p ON p.id = r.parent
+    JOIN "changes" ch ON ch.diff = r.id
+    JOIN "editfiles" e ON e.id = ch.file
+    WHERE c.hash = :hash AND c.repo = :repo
+    ORDER BY r.id, e.path
+    "# })?;
+    let rows = stmt
+        .query_map(named_params! {":hash": hash, ":repo": repo_id}, |r| {
+            Ok(Change {
+                parent: r.get(0)?,
+                mode: mode_char(r.get(1)?),
+                path: r.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
+
+/// Letter of `git diff --name-status` for the stored mode
+fn mode_char(mode: i64) -> char {
+    match mode {
+        1 => 'A',
+        2 => 'D',
+        3 => 'M',
+        4 => 'R',
+        5 => 'C',
+        8 => 'T',
+        9 => 'X',
+        10 => 'U',
+        _ => ' ',
+    }
+}
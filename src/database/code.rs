@@ -0,0 +1,86 @@
+use anyhow::Result;
+use indoc::indoc;
+use rusqlite::{named_params, Connection, Transaction};
+use std::collections::HashMap;
+
+pub struct Hit {
+    pub repo: String,
+    pub path: String,
+    pub content: String,
+}
+
+/// Indexed files of the repository, path -> (id, blob hash)
+pub fn get_blobs(conn: &Connection, repo_id: i64) -> Result<HashMap<String, (i64, String)>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT "id", "path", "blob" FROM "codefiles"
+        WHERE "repo" = :repo
+        "# })?;
+    let blobs = stmt
+        .query_map(named_params! {":repo": repo_id}, |r| {
+            Ok((r.get::<_, String>(1)?, (r.get(0)?, r.get(2)?)))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(blobs)
+}
+
+/// Binary and oversized files pass `None` as content, they are
+/// remembered by blob hash but never searched.
+pub fn insert(
+    tx: &Transaction,
+    repo_id: i64,
+    path: &str,
+    blob: &str,
+    content: Option<&str>,
+) -> Result<()> {
+    let mut stmt = tx.prepare_cached(indoc! { r#"
+        INSERT INTO "codefiles" (repo, path, blob)
+        VALUES (:repo, :path, :blob)
+        "# })?;
+    let id = stmt.insert(named_params! {":repo": repo_id, ":path": path, ":blob": blob})?;
+
+    if let Some(content) = content {
+        let mut stmt = tx.prepare_cached(indoc! { r#"
+            INSERT INTO "codesearch" (rowid, content)
+            VALUES (:id, :content)
+            "# })?;
+        stmt.execute(named_params! {":id": id, ":content": content})?;
+    }
+    Ok(())
+}
+
+pub fn delete(tx: &Transaction, id: i64) -> Result<()> {
+    // codesearch row is removed by trigger
+    let mut stmt = tx.prepare_cached(indoc! { r#"
+        DELETE FROM "codefiles" WHERE "id" = :id
+        "# })?;
+    stmt.execute(named_params! {":id": id})?;
+    Ok(())
+}
+
+/// Files containing `text` (case-insensitive), at least three characters
+/// are required by the trigram tokenizer.
+pub fn search(conn: &Connection, text: &str, repo_id: Option<i64>, limit: u32) -> Result<Vec<Hit>> {
+    let pattern = format!("\"{}\"", text.replace('"', "\"\""));
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT r.name, f.path, s.content FROM "codesearch" s
+        JOIN "codefiles" f ON f.id = s.rowid
+        JOIN "repositories" r ON r.id = f.repo
+        WHERE "codesearch" MATCH :pattern
+            AND (:repo IS NULL AND NOT r.hidden OR f.repo = :repo)
+        ORDER BY r.name, f.path
+        LIMIT :limit
+        "# })?;
+    let hits = stmt
+        .query_map(
+            named_params! {":pattern": pattern, ":repo": repo_id, ":limit": limit},
+            |r| {
+                Ok(Hit {
+                    repo: r.get(0)?,
+                    path: r.get(1)?,
+                    content: r.get(2)?,
+                })
+            },
+        )?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(hits)
+}
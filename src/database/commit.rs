@@ -1,9 +1,14 @@
-use super::{contributor, datetime, message};
+use super::{
+    change::{self, File},
+    contributor::{self, Person},
+    message,
+};
 use anyhow::Result;
 use core::str;
 use git2::Commit;
 use indoc::indoc;
-use rusqlite::{named_params, OptionalExtension, Transaction};
+use rusqlite::{named_params, Connection, OptionalExtension, Transaction};
+use serde::Serialize;
 
 pub fn get_id(tx: &Transaction, hash: &str, repo_id: i64) -> Result<Option<i64>> {
     let mut stmt = tx.prepare_cached(indoc! { r#"
@@ -19,6 +24,21 @@ pub fn get_id(tx: &Transaction, hash: &str, repo_id: i64) -> Result<Option<i64>>
     Ok(id)
 }
 
+pub fn get_id_by_hash(conn: &Connection, hash: &str, repo_id: i64) -> Result<Option<i64>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT "id" FROM "commits"
+        WHERE "hash" = :hash AND "repo" = :repo
+        LIMIT 1
+        "# })?;
+    let id = stmt
+        .query_row(named_params! {":hash": hash, ":repo": repo_id}, |r| {
+            r.get::<_, i64>(0)
+        })
+        .optional()?;
+    Ok(id)
+}
+
+#[allow(dead_code)]
 pub fn get_tree_by_id(tx: &Transaction, id: i64, repo_id: i64) -> Result<Option<String>> {
     let mut stmt = tx.prepare_cached(indoc! { r#"
         SELECT "tree" FROM "commits"
@@ -33,6 +53,7 @@ pub fn get_tree_by_id(tx: &Transaction, id: i64, repo_id: i64) -> Result<Option<
     Ok(tree_hash)
 }
 
+#[allow(dead_code)]
 pub fn get_tree_by_hash(tx: &Transaction, hash: &str, repo_id: i64) -> Result<Option<String>> {
     let mut stmt = tx.prepare_cached(indoc! { r#"
         SELECT "tree" FROM "commits"
@@ -47,20 +68,36 @@ pub fn get_tree_by_hash(tx: &Transaction, hash: &str, repo_id: i64) -> Result<Op
     Ok(tree_hash)
 }
 
-pub fn insert(tx: &Transaction, obj: &Commit, repo_id: i64) -> Result<i64> {
-    let hash = obj.id().to_string();
-
-    // let id = get_id(tx, &hash, repo_id)?;
+/// Commit read from git, owned so a worker can hand it to the writer
+pub struct Record {
+    pub hash: String,
+    pub tree: String,
+    pub committer: Person,
+    pub author: Person,
+    pub msg: String,
+    /// hash of each parent and files changed against it
+    pub parents: Vec<(String, Vec<File>)>,
+}
 
-    // if let Some(_) = id {
-    //     return Ok(None);
-    // }
+impl Record {
+    pub fn new(obj: &Commit, parents: Vec<(String, Vec<File>)>) -> Result<Self> {
+        Ok(Record {
+            hash: obj.id().to_string(),
+            tree: obj.tree_id().to_string(),
+            committer: Person::new(&obj.committer())?,
+            author: Person::new(&obj.author())?,
+            msg: String::from_utf8_lossy(obj.message_bytes()).into_owned(),
+            parents,
+        })
+    }
+}
 
-    let tree_id = obj.tree_id().to_string();
-    let committer = contributor::get_id(tx, &obj.committer())?;
-    let date = datetime::get_time(obj.time())?;
-    let msg = obj.message().unwrap_or_default();
-    let msg_id = message::insert(tx, msg, &obj.author(), repo_id)?;
+pub fn insert(tx: &Transaction, obj: &Record, repo_id: i64) -> Result<i64> {
+    let hash = &obj.hash;
+    let tree_id = &obj.tree;
+    let committer = contributor::get_id(tx, &obj.committer)?;
+    let date = obj.committer.date;
+    let msg_id = message::insert(tx, &obj.msg, &obj.author, repo_id)?;
 
     let mut stmt = tx.prepare_cached(indoc! { r#"
         INSERT INTO "commits" (hash, repo, tree, date, c7r_id, msg_id)
@@ -77,3 +114,83 @@ pub fn insert(tx: &Transaction, obj: &Commit, repo_id: i64) -> Result<i64> {
 
     Ok(id)
 }
+
+#[derive(Serialize)]
+pub struct Log {
+    pub hash: String,
+    pub date: String,
+    pub author: String,
+    pub mail: String,
+    pub msg: String,
+}
+
+/// Walk parents from commit `start` newest first, like `git log`. With
+/// `path` only commits changing it or files below it, like `git log --
+/// path`, root commits have no changes recorded.
+pub fn log(
+    conn: &Connection,
+    repo_id: i64,
+    start: &str,
+    path: Option<&str>,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<Log>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        WITH RECURSIVE walk(id, time) AS (
+            SELECT c.id, unixepoch(c.date) FROM "commits" c
+            WHERE c.repo = :repo AND c.hash = :start
+            UNION
+            SELECT c.id, unixepoch(c.date) FROM walk
+            JOIN "relations" r ON r.child = walk.id
+            JOIN "commits" c ON c.id = r.parent
+            ORDER BY 2 DESC
+            LIMIT :walk
+        )
+        SELECT c.hash, strftime('%Y-%m-%dT%H:%M:%SZ', walk.time, 'unixepoch'),
+            p.name, p.mail, m.msg
+        FROM walk
+        JOIN "commits" c ON c.id = walk.id
+        JOIN "messages" m ON m.id = c.msg_id
+        JOIN "contributors" p ON p.id = m.author
+        WHERE :path IS NULL OR EXISTS (
+            SELECT 1 FROM "relations" r
+            JOIN "changes" x ON x.diff = r.id
+            JOIN "editfiles" e ON e.id = x.file
+            WHERE r.child = walk.id AND e.repo = :repo
+                AND (e.path = :path OR e.path >= :lower AND e.path < :upper)
+        )
+        ORDER BY walk.time DESC
+        LIMIT :limit OFFSET :offset
+        "# })?;
+    let path = path.map(|p| p.trim_matches('/')).filter(|p| !p.is_empty());
+    let (lower, upper) = change::prefix_range(path.unwrap_or_default());
+    // without a path the walk can stop at the page, -1 walks everything
+    let walk = match path {
+        Some(_) => -1,
+        None => i64::from(limit) + i64::from(offset),
+    };
+    let rows = stmt
+        .query_map(
+            named_params! {
+                ":repo": repo_id,
+                ":start": start,
+                ":path": path,
+                ":lower": lower,
+                ":upper": upper,
+                ":walk": walk,
+                ":limit": limit,
+                ":offset": offset,
+            },
+            |r| {
+                Ok(Log {
+                    hash: r.get(0)?,
+                    date: r.get(1)?,
+                    author: r.get(2)?,
+                    mail: r.get(3)?,
+                    msg: r.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                })
+            },
+        )?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
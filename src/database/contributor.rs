@@ -1,11 +1,31 @@
+use super::datetime;
 use anyhow::Result;
 use git2::Signature;
 use indoc::indoc;
-use rusqlite::{named_params, OptionalExtension, Transaction};
+use rusqlite::{named_params, Connection, OptionalExtension, Transaction};
+use serde::Serialize;
+use time::OffsetDateTime;
 
-pub fn get_id(tx: &Transaction, sig: &Signature) -> Result<i64> {
-    let name = sig.name();
-    let mail = sig.email();
+/// Owned signature, can be sent to the database writer
+pub struct Person {
+    pub name: String,
+    pub mail: String,
+    pub date: OffsetDateTime,
+}
+
+impl Person {
+    pub fn new(sig: &Signature) -> Result<Self> {
+        Ok(Person {
+            name: String::from_utf8_lossy(sig.name_bytes()).into_owned(),
+            mail: String::from_utf8_lossy(sig.email_bytes()).into_owned(),
+            date: datetime::get_time(sig.when())?,
+        })
+    }
+}
+
+pub fn get_id(tx: &Transaction, person: &Person) -> Result<i64> {
+    let name = &person.name;
+    let mail = &person.mail;
 
     let mut stmt = tx.prepare_cached(indoc! { r#"
         SELECT "id" FROM "contributors"
@@ -28,3 +48,105 @@ pub fn get_id(tx: &Transaction, sig: &Signature) -> Result<i64> {
         Ok(id)
     }
 }
+
+#[derive(Serialize)]
+pub struct Ranked {
+    pub id: i64,
+    pub name: String,
+    pub mail: String,
+    pub commits: i64,
+    pub files: i64,
+    /// lines of non-merge commits, `None` without line counts
+    pub added: Option<i64>,
+    pub removed: Option<i64>,
+    pub first: String,
+    pub last: String,
+}
+
+#[derive(Serialize)]
+pub struct Authored {
+    pub repo: String,
+    pub hash: String,
+    pub date: String,
+    pub msg: String,
+}
+
+/// Authors of the repository ranked by commit count, `files` counts
+/// changed files against every parent.
+pub fn rank(conn: &Connection, repo_id: i64) -> Result<Vec<Ranked>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT p.id, p.name, p.mail, COUNT(*) AS n,
+            COALESCE(SUM(f.n), 0), SUM(f.added), SUM(f.removed),
+            strftime('%Y-%m-%dT%H:%M:%SZ', MIN(unixepoch(c.date)), 'unixepoch'),
+            strftime('%Y-%m-%dT%H:%M:%SZ', MAX(unixepoch(c.date)), 'unixepoch')
+        FROM "commits" c
+        JOIN "messages" m ON m.id = c.msg_id
+        JOIN "contributors" p ON p.id = m.author
+        LEFT JOIN (
+            SELECT r.child, COUNT(*) AS n,
+                SUM(ch.added) AS added, SUM(ch.removed) AS removed
+            FROM "changes" ch
+            JOIN "relations" r ON r.id = ch.diff
+            WHERE r.repo = :repo
+            GROUP BY r.child
+        ) f ON f.child = c.id
+        WHERE c.repo = :repo
+        GROUP BY p.id
+        ORDER BY n DESC, p.name
+        "# })?;
+    let rows = stmt
+        .query_map(named_params! {":repo": repo_id}, |r| {
+            Ok(Ranked {
+                id: r.get(0)?,
+                name: r.get(1)?,
+                mail: r.get(2)?,
+                commits: r.get(3)?,
+                files: r.get(4)?,
+                added: r.get(5)?,
+                removed: r.get(6)?,
+                first: r.get(7)?,
+                last: r.get(8)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
+
+pub fn get(conn: &Connection, id: i64) -> Result<Option<(String, String)>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT "name", "mail" FROM "contributors"
+        WHERE "id" = :id
+        LIMIT 1
+        "# })?;
+    let sig = stmt
+        .query_row(named_params! {":id": id}, |r| Ok((r.get(0)?, r.get(1)?)))
+        .optional()?;
+    Ok(sig)
+}
+
+/// Commits written by the contributor across all repositories, newest first
+pub fn commits(conn: &Connection, id: i64, limit: u32, offset: u32) -> Result<Vec<Authored>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT r.name, c.hash, strftime('%Y-%m-%dT%H:%M:%SZ', unixepoch(c.date), 'unixepoch'), m.msg
+        FROM "commits" c
+        JOIN "messages" m ON m.id = c.msg_id
+        JOIN "repositories" r ON r.id = c.repo
+        WHERE m.author = :id AND NOT r.hidden
+        ORDER BY unixepoch(c.date) DESC
+        LIMIT :limit OFFSET :offset
+        "# })?;
+    let rows = stmt
+        .query_map(
+            named_params! {":id": id, ":limit": limit, ":offset": offset},
+            |r| {
+                Ok(Authored {
+                    repo: r.get(0)?,
+                    hash: r.get(1)?,
+                    date: r.get(2)?,
+                    msg: r.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                })
+            },
+        )?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
@@ -1,9 +1,17 @@
 use anyhow::Result;
 use git2::Time;
-use time::{OffsetDateTime, UtcOffset};
+use time::{macros::format_description, OffsetDateTime, UtcOffset};
 
 pub fn get_time(gt: Time) -> Result<OffsetDateTime> {
     let offset = UtcOffset::from_whole_seconds(gt.offset_minutes() * 60)?;
     let time = OffsetDateTime::from_unix_timestamp(gt.seconds())?.to_offset(offset);
     Ok(time)
 }
+
+/// Same as `git log --date=iso`
+pub fn to_iso(gt: Time) -> Result<String> {
+    let format = format_description!(
+        "[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour sign:mandatory][offset_minute]"
+    );
+    Ok(get_time(gt)?.format(format)?)
+}
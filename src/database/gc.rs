@@ -0,0 +1,119 @@
+use anyhow::Result;
+use indoc::indoc;
+use rusqlite::Transaction;
+
+/// Rows removed by `collect`
+#[derive(Default)]
+pub struct Reclaimed {
+    pub commits: usize,
+    pub relations: usize,
+    pub changes: usize,
+    pub messages: usize,
+    pub contributors: usize,
+    pub editfiles: usize,
+}
+
+/// Remove commits of `repos` not reachable from the recorded tips of their
+/// repository, after removed or force-pushed history, then rows of `repos`
+/// nothing refers to. Repositories whose last update failed are left alone.
+/// Stale branches and tags must be cleaned up first, they still point to the
+/// old commits, and the activity rollup of `repos` is rebuilt after.
+pub fn collect(tx: &Transaction, repos: &[i64]) -> Result<Reclaimed> {
+    let mut reclaimed = Reclaimed::default();
+    tx.execute_batch(r#"CREATE TEMP TABLE "collected" (id INTEGER PRIMARY KEY)"#)?;
+    let mut stmt = tx.prepare(r#"INSERT INTO "collected" (id) VALUES (?1)"#)?;
+    for id in repos {
+        stmt.execute([id])?;
+    }
+    tx.execute_batch(indoc! { r#"
+        CREATE TEMP TABLE "unreachable" AS
+        WITH RECURSIVE walk(id) AS (
+            SELECT c.id FROM "reftips" t
+            JOIN "commits" c ON c.repo = t.repo AND c.hash = t.hash
+            WHERE t.repo IN (SELECT id FROM "collected")
+            UNION
+            SELECT r.parent FROM walk
+            JOIN "relations" r ON r.child = walk.id
+        )
+        SELECT c.id, c.repo FROM "commits" c
+        JOIN "repositories" p ON p.id = c.repo
+        WHERE p.id IN (SELECT id FROM "collected")
+            AND p.error IS NULL AND c.id NOT IN walk;
+
+        CREATE TEMP TABLE "unrelated" AS
+        SELECT r.id FROM "relations" r
+        WHERE r.child IN (SELECT id FROM "unreachable")
+            OR r.parent IN (SELECT id FROM "unreachable");
+
+        -- contributors are shared, only these may be left unused
+        CREATE TEMP TABLE "authors" AS
+        SELECT m.author AS id FROM "messages" m
+        WHERE m.repo IN (SELECT id FROM "collected")
+        UNION
+        SELECT c.c7r_id FROM "commits" c
+        WHERE c.id IN (SELECT id FROM "unreachable");
+        "# })?;
+
+    // delete explicitly rather than by cascade, to count the rows
+    reclaimed.changes = tx.execute(
+        indoc! { r#"
+        DELETE FROM "changes" WHERE diff IN (SELECT id FROM "unrelated")
+        "# },
+        [],
+    )?;
+    reclaimed.relations = tx.execute(
+        indoc! { r#"
+        DELETE FROM "relations" WHERE id IN (SELECT id FROM "unrelated")
+        "# },
+        [],
+    )?;
+    reclaimed.commits = tx.execute(
+        indoc! { r#"
+        DELETE FROM "commits" WHERE id IN (SELECT id FROM "unreachable")
+        "# },
+        [],
+    )?;
+
+    reclaimed.messages = tx.execute(
+        indoc! { r#"
+        DELETE FROM "messages"
+        WHERE repo IN (SELECT id FROM "collected")
+            AND id NOT IN (SELECT msg_id FROM "commits")
+            AND id NOT IN (SELECT msg_id FROM "tags" WHERE msg_id IS NOT NULL)
+        "# },
+        [],
+    )?;
+    // the rollup still counts removed commits, the caller rebuilds it
+    tx.execute(
+        indoc! { r#"
+        DELETE FROM "activities" WHERE repo IN (SELECT id FROM "collected")
+        "# },
+        [],
+    )?;
+    reclaimed.contributors = tx.execute(
+        indoc! { r#"
+        DELETE FROM "contributors"
+        WHERE id IN (SELECT id FROM "authors")
+            AND id NOT IN (SELECT author FROM "messages")
+            AND id NOT IN (SELECT c7r_id FROM "commits")
+            AND id NOT IN (SELECT author FROM "activities")
+        "# },
+        [],
+    )?;
+    reclaimed.editfiles = tx.execute(
+        indoc! { r#"
+        DELETE FROM "editfiles"
+        WHERE repo IN (SELECT id FROM "collected")
+            AND id NOT IN (SELECT file FROM "changes")
+        "# },
+        [],
+    )?;
+
+    tx.execute_batch(indoc! { r#"
+        DROP TABLE "collected";
+        DROP TABLE "unreachable";
+        DROP TABLE "unrelated";
+        DROP TABLE "authors";
+        "# })?;
+    Ok(reclaimed)
+}
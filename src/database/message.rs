@@ -1,12 +1,11 @@
-use super::{contributor, datetime};
+use super::contributor::{self, Person};
 use anyhow::Result;
-use git2::Signature;
 use indoc::indoc;
 use rusqlite::{named_params, Transaction};
 
-pub fn insert(tx: &Transaction, msg: &str, sig: &Signature, repo_id: i64) -> Result<i64> {
-    let date = datetime::get_time(sig.when())?;
-    let author_id = contributor::get_id(tx, sig)?;
+pub fn insert(tx: &Transaction, msg: &str, author: &Person, repo_id: i64) -> Result<i64> {
+    let date = author.date;
+    let author_id = contributor::get_id(tx, author)?;
     let mut stmt = tx.prepare_cached(indoc! { r#"
         INSERT INTO "messages" (repo, author, msg, date)
         VALUES (:repo, :author, :msg, :date)
@@ -2,147 +2,115 @@ use crate::config::{Config, Mode};
 use anyhow::Result;
 use indoc::indoc;
 use rusqlite::{Connection, OpenFlags};
+use std::time::Duration;
 
+pub mod activity;
+pub mod branch;
 pub mod change;
+pub mod code;
 pub mod commit;
 pub mod contributor;
 pub mod datetime;
+pub mod gc;
 pub mod message;
+pub mod reftip;
 pub mod relation;
 pub mod repository;
-// pub mod branch;
-// pub mod tag;
+pub mod schema;
+pub mod tag;
+
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Wait for the transaction of another update, the scheduler or a hook
+const WRITE_TIMEOUT: Duration = Duration::from_secs(120);
 
 impl Config {
     pub fn open_db(&self) -> Result<Connection> {
         let db_name = self.path.join("rogit.db");
         let conn = match self.mode {
-            Mode::Update => {
-                let conn = Connection::open(db_name)?;
-                init_table(&conn)?;
-                conn
-            }
-            Mode::Server => {
+            Mode::Update => self.open_db_rw()?,
+            _ => {
                 let flags = OpenFlags::SQLITE_OPEN_READ_ONLY;
-                Connection::open_with_flags(db_name, flags)?
+                let conn = Connection::open_with_flags(db_name, flags)?;
+                // only while the update checkpoints or recovers the wal
+                conn.busy_timeout(BUSY_TIMEOUT)?;
+                conn
             }
         };
         Ok(conn)
     }
-}
-
-fn init_table(conn: &Connection) -> Result<()> {
-    conn.pragma_update(None, "synchronous", "FULL")?;
-    conn.pragma_update(None, "foreign_keys", "ON")?;
-    conn.pragma_update(None, "journal_mode", "MEMORY")?;
-
-    conn.execute_batch(indoc! {r#"
-        CREATE TABLE IF NOT EXISTS "repositories" (
-            id      INTEGER PRIMARY KEY AUTOINCREMENT,
-            name    TEXT NOT NULL UNIQUE,
-            show    TEXT,
-            head    TEXT NOT NULL,
-            fake    INTEGER DEFAULT 0   -- used to delete expired
-        ) STRICT;
-
-        UPDATE "repositories" SET fake = 1;
-
-        CREATE TABLE IF NOT EXISTS contributors (
-            id      INTEGER PRIMARY KEY AUTOINCREMENT,
-            name    TEXT NOT NULL,
-            mail    TEXT NOT NULL,
-            UNIQUE(name, mail)
-        ) STRICT;
-
-        CREATE TABLE IF NOT EXISTS "messages" (
-            id      INTEGER PRIMARY KEY AUTOINCREMENT,
-            repo    INTEGER NOT NULL,
-            author  INTEGER NOT NULL,
-            msg     TEXT,
-            date    TEXT,               -- same as date in commit
-            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
-            FOREIGN KEY(author) REFERENCES contributors(id)
-        ) STRICT;
-
-        CREATE TABLE IF NOT EXISTS "commits" (
-            id      INTEGER PRIMARY KEY AUTOINCREMENT,
-            hash    TEXT NOT NULL,
-            repo    INTEGER NOT NULL,
-            tree    TEXT NOT NULL,
-            date    TEXT,               -- commit date
-            c7r_id  INTEGER NOT NULL,   -- committer
-            msg_id  INTEGER NOT NULL,   -- think author write it
-            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
-            FOREIGN KEY(c7r_id) REFERENCES contributors(id),
-            FOREIGN KEY(msg_id) REFERENCES messages(id),
-            UNIQUE(repo, hash)
-        ) STRICT;
 
-        CREATE TABLE IF NOT EXISTS "relations" (
-            id      INTEGER PRIMARY KEY AUTOINCREMENT,
-            repo    INTEGER NOT NULL,
-            child   INTEGER NOT NULL,
-            parent  INTEGER NOT NULL,
-            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
-            FOREIGN KEY(child) REFERENCES commits(id) ON DELETE CASCADE,
-            FOREIGN KEY(parent) REFERENCES commits(id) ON DELETE CASCADE,
-            UNIQUE(repo, child, parent)
-        ) STRICT;
-
-        CREATE INDEX IF NOT EXISTS idx_relations_child ON relations(child);
-        CREATE INDEX IF NOT EXISTS idx_relations_parent ON relations(parent);
-
-        CREATE TABLE IF NOT EXISTS "editfiles" (
-            id      INTEGER PRIMARY KEY AUTOINCREMENT,
-            path    TEXT NOT NULL UNIQUE
-        ) STRICT;
-
-        CREATE TABLE IF NOT EXISTS "changes" (
-            id      INTEGER PRIMARY KEY AUTOINCREMENT,
-            diff    INTEGER NOT NULL,
-            mode    INTEGER NOT NULL,
-            file    INTEGER NOT NULL,
-            FOREIGN KEY(diff) REFERENCES relations(id) ON DELETE CASCADE
-            FOREIGN KEY(file) REFERENCES editfiles(id) ON DELETE CASCADE
-        ) STRICT;
+    /// Connection of the update, also used by the scheduler in server mode
+    pub fn open_db_rw(&self) -> Result<Connection> {
+        let conn = Connection::open(self.path.join("rogit.db"))?;
+        conn.pragma_update(None, "synchronous", &self.synchronous)?;
+        conn.busy_timeout(WRITE_TIMEOUT)?;
+        init_table(&conn)?;
+        Ok(conn)
+    }
 
-        CREATE TABLE IF NOT EXISTS "branches" (
-            id      INTEGER PRIMARY KEY AUTOINCREMENT,
-            name    TEXT NOT NULL,
-            hash    INTEGER NOT NULL,   -- target commit
-            repo    INTEGER NOT NULL,
-            fake    INTEGER DEFAULT 0,
-            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
-            FOREIGN KEY(hash) REFERENCES commits(id),
-            UNIQUE(repo, name)
-        ) STRICT;
+    /// Upgrade the schema of rogit.db without updating repositories
+    pub fn migrate_db(&self) -> Result<()> {
+        let conn = Connection::open(self.path.join("rogit.db"))?;
+        let count = schema::migrate(&conn)?;
+        println!(
+            "[database] schema: v{}, {} migrations applied",
+            schema::VERSION,
+            count
+        );
+        Ok(())
+    }
 
-        UPDATE "branches" SET fake = 1;
+    /// Fail unless rogit.db has the schema this binary expects
+    pub fn check_db(&self) -> Result<()> {
+        let conn = self.open_db()?;
+        let version = schema::version(&conn)?;
+        println!(
+            "[database] schema: v{}, binary: v{}",
+            version,
+            schema::VERSION
+        );
+        schema::check(&conn)
+    }
+}
 
-        CREATE TABLE IF NOT EXISTS "tags" (
-            id      INTEGER PRIMARY KEY AUTOINCREMENT,
-            name    TEXT NOT NULL,
-            hash    INTEGER NOT NULL,   -- target commit
-            repo    INTEGER NOT NULL,
-            msg_id  INTEGER,            -- only tag object has
-            fake    INTEGER DEFAULT 0,
-            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
-            FOREIGN KEY(hash) REFERENCES commits(id),
-            FOREIGN KEY(msg_id) REFERENCES messages(id),
-            UNIQUE(repo, name)
-        ) STRICT;
+/// Readers see the last committed snapshot while the update writes
+fn init_table(conn: &Connection) -> Result<()> {
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    schema::migrate(conn)?;
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+    Ok(())
+}
 
-        UPDATE "tags" SET fake = 1;
+/// Move the wal back into rogit.db and truncate it, returns false if
+/// readers kept some frames from being copied.
+pub fn checkpoint(conn: &Connection) -> Result<bool> {
+    let busy = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |r| {
+        r.get::<_, i64>(0)
+    })?;
+    Ok(busy == 0)
+}
 
-    "#})?;
+/// Mark branches and tags of the repository as expired, the update
+/// revives the ones still alive
+pub fn mark_refs(conn: &Connection, repo_id: i64) -> Result<()> {
+    conn.execute_batch(&format!(
+        indoc! { r#"
+        UPDATE "branches" SET fake = 1 WHERE repo = {id};
+        UPDATE "tags" SET fake = 1 WHERE repo = {id};
+        "# },
+        id = repo_id
+    ))?;
     Ok(())
 }
 
-pub fn cleanup(conn: &Connection) -> Result<()> {
-    conn.execute_batch(indoc! { r#"
-        DELETE FROM "repositories" WHERE fake = 1;
-        DELETE FROM "branches" WHERE fake = 1;
-        DELETE FROM "tags" WHERE fake = 1;
-        "# })?;
+/// Delete branches and tags of the repository still marked as expired
+pub fn cleanup_refs(conn: &Connection, repo_id: i64) -> Result<()> {
+    conn.execute_batch(&format!(
+        indoc! { r#"
+        DELETE FROM "branches" WHERE repo = {id} AND fake = 1;
+        DELETE FROM "tags" WHERE repo = {id} AND fake = 1;
+        "# },
+        id = repo_id
+    ))?;
     Ok(())
 }
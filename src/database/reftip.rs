@@ -0,0 +1,29 @@
+use anyhow::Result;
+use indoc::indoc;
+use rusqlite::{named_params, Connection, Transaction};
+
+/// Tips recorded by the last update, their history is already stored
+pub fn list(conn: &Connection, repo_id: i64) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT DISTINCT "hash" FROM "reftips"
+        WHERE "repo" = :repo
+        "# })?;
+    let rows = stmt
+        .query_map(named_params! {":repo": repo_id}, |r| r.get::<_, String>(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
+
+/// Forget old tips and record `(ref name, commit hash)` of this update
+pub fn replace(tx: &Transaction, tips: &[(String, String)], repo_id: i64) -> Result<()> {
+    tx.prepare_cached(r#"DELETE FROM "reftips" WHERE "repo" = :repo"#)?
+        .execute(named_params! {":repo": repo_id})?;
+    let mut stmt = tx.prepare_cached(indoc! { r#"
+        INSERT INTO "reftips" (repo, name, hash)
+        VALUES (:repo, :name, :hash)
+        "# })?;
+    for (name, hash) in tips {
+        stmt.execute(named_params! {":repo": repo_id, ":name": name, ":hash": hash})?;
+    }
+    Ok(())
+}
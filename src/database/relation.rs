@@ -3,6 +3,7 @@ use core::str;
 use indoc::indoc;
 use rusqlite::{named_params, OptionalExtension, Transaction};
 
+#[allow(dead_code)]
 pub fn get_id(
     tx: &Transaction,
     parent_id: i64,
@@ -1,32 +1,225 @@
-use anyhow::Result;
+use crate::config::RepoMeta;
+use anyhow::{anyhow, Result};
 use indoc::indoc;
-use rusqlite::{named_params, Connection};
+use rusqlite::{named_params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
 
-pub fn get_id(conn: &Connection, name: &str) -> Result<i64> {
-    let id = conn.query_row(
-        indoc! { r#"
+/// None until the first update of the repository
+pub fn get_id(conn: &Connection, name: &str) -> Result<Option<i64>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
         SELECT "id" FROM "repositories"
         WHERE "name" = :name
         LIMIT 1
-        "# },
-        named_params! {":name": name},
-        |r| r.get::<_, i64>(0),
-    )?;
+        "# })?;
+    let id = stmt
+        .query_row(named_params! {":name": name}, |r| r.get::<_, i64>(0))
+        .optional()?;
     Ok(id)
 }
 
-pub fn insert(conn: &Connection, name: &str, show: &str, head: &str) -> Result<()> {
+pub fn insert(
+    conn: &Connection,
+    name: &str,
+    show: &str,
+    head: &str,
+    size: u64,
+    meta: &RepoMeta,
+) -> Result<()> {
     conn.execute(
         indoc! { r#"
-        INSERT INTO "repositories" (name, show, head, fake)
-        VALUES (:name, :show, :head, 0)
+        INSERT INTO "repositories"
+            (name, show, head, size, display_name, category, hidden, fake)
+        VALUES (:name, :show, :head, :size, :display_name, :category, :hidden, 0)
         ON CONFLICT(name)
         DO UPDATE SET
             show = EXCLUDED.show,
             head = EXCLUDED.head,
+            size = EXCLUDED.size,
+            display_name = EXCLUDED.display_name,
+            category = EXCLUDED.category,
+            hidden = EXCLUDED.hidden,
+            error = NULL,
+            fake = 0;
+        "# },
+        named_params! {
+            ":name": name,
+            ":show": show,
+            ":head": head,
+            ":size": size,
+            ":display_name": meta.display_name,
+            ":category": meta.category,
+            ":hidden": meta.hidden,
+        },
+    )?;
+    Ok(())
+}
+
+/// Delete repositories not in `names`, gone from the rogit path
+pub fn prune(conn: &Connection, names: &[&str]) -> Result<usize> {
+    let mut count = 0;
+    for r in list(conn, true)? {
+        if names.contains(&r.name.as_str()) {
+            continue;
+        }
+        conn.execute(
+            r#"DELETE FROM "repositories" WHERE "id" = :id"#,
+            named_params! {":id": r.id},
+        )?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Record why the update of `name` failed. Rows of the last good update
+/// are kept alive so cleanup does not drop them.
+pub fn fail(conn: &Connection, name: &str, error: &str) -> Result<()> {
+    conn.execute(
+        indoc! { r#"
+        INSERT INTO "repositories" (name, head, error, fake)
+        VALUES (:name, '', :error, 0)
+        ON CONFLICT(name)
+        DO UPDATE SET
+            error = EXCLUDED.error,
             fake = 0;
         "# },
-        named_params! {":name": name, ":show": show, ":head": head},
+        named_params! {":name": name, ":error": error},
     )?;
+    let id = get_id(conn, name)?.ok_or_else(|| anyhow!("repository not found: {}", name))?;
+    for table in ["branches", "tags"] {
+        conn.execute(
+            &format!(r#"UPDATE "{}" SET fake = 0 WHERE "repo" = :repo"#, table),
+            named_params! {":repo": id},
+        )?;
+    }
     Ok(())
 }
+
+#[derive(Serialize)]
+pub struct Repository {
+    pub id: i64,
+    pub name: String,
+    pub show: String,
+    pub head: String,
+}
+
+/// Repositories by name, `hidden` ones only if asked for
+pub fn list(conn: &Connection, hidden: bool) -> Result<Vec<Repository>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT "id", "name", "show", "head" FROM "repositories"
+        WHERE :hidden OR NOT "hidden"
+        ORDER BY "name"
+        "# })?;
+    let rows = stmt
+        .query_map(named_params! {":hidden": hidden}, |r| {
+            Ok(Repository {
+                id: r.get(0)?,
+                name: r.get(1)?,
+                show: r.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                head: r.get(3)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
+
+/// None until the first update of the repository
+pub fn get(conn: &Connection, name: &str) -> Result<Option<Repository>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT "id", "name", "show", "head" FROM "repositories"
+        WHERE "name" = :name
+        LIMIT 1
+        "# })?;
+    let repo = stmt
+        .query_row(named_params! {":name": name}, |r| {
+            Ok(Repository {
+                id: r.get(0)?,
+                name: r.get(1)?,
+                show: r.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                head: r.get(3)?,
+            })
+        })
+        .optional()?;
+    Ok(repo)
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    #[default]
+    Name,
+    /// latest commit on head first
+    Activity,
+}
+
+#[derive(Serialize)]
+pub struct Overview {
+    pub name: String,
+    /// `display-name` of the config file, else the name
+    pub display_name: String,
+    /// `category` of the config file, else the directory of nested names
+    pub category: Option<String>,
+    pub show: String,
+    pub head: String,
+    /// bytes on disk
+    pub size: i64,
+    pub commits: i64,
+    /// tip of head branch
+    pub last_date: Option<String>,
+    pub last_author: Option<String>,
+    /// reason of the last failed update
+    pub error: Option<String>,
+}
+
+/// Repositories whose name or description contains `filter`, and the
+/// one named `name` if given. Hidden ones are only found by name.
+pub fn overview(
+    conn: &Connection,
+    name: Option<&str>,
+    filter: Option<&str>,
+    sort: Sort,
+) -> Result<Vec<Overview>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT r.name, r.show, r.head, COALESCE(r.size, 0),
+            (SELECT COUNT(*) FROM "commits" n WHERE n.repo = r.id),
+            strftime('%Y-%m-%dT%H:%M:%SZ', unixepoch(c.date), 'unixepoch'), p.name,
+            r.error, COALESCE(r.display_name, r.name), r.category
+        FROM "repositories" r
+        LEFT JOIN "branches" b ON b.repo = r.id AND b.name = r.head
+        LEFT JOIN "commits" c ON c.id = b.hash
+        LEFT JOIN "messages" m ON m.id = c.msg_id
+        LEFT JOIN "contributors" p ON p.id = m.author
+        WHERE (:name IS NULL AND NOT r.hidden OR r.name = :name)
+            AND (:filter IS NULL
+                OR instr(lower(r.name), lower(:filter)) > 0
+                OR instr(lower(r.display_name), lower(:filter)) > 0
+                OR instr(lower(r.show), lower(:filter)) > 0)
+        ORDER BY CASE WHEN :activity THEN unixepoch(c.date) END DESC, r.name
+        "# })?;
+    let params = named_params! {
+        ":name": name,
+        ":filter": filter,
+        ":activity": matches!(sort, Sort::Activity),
+    };
+    let rows = stmt
+        .query_map(params, |r| {
+            let name: String = r.get(0)?;
+            let category = r.get::<_, Option<String>>(9)?.or_else(|| {
+                let (dir, _) = name.rsplit_once('/')?;
+                Some(dir.to_owned())
+            });
+            Ok(Overview {
+                name,
+                display_name: r.get(8)?,
+                category,
+                show: r.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                head: r.get(2)?,
+                size: r.get(3)?,
+                commits: r.get(4)?,
+                last_date: r.get(5)?,
+                last_author: r.get(6)?,
+                error: r.get(7)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
@@ -0,0 +1,335 @@
+use anyhow::{anyhow, Result};
+use indoc::indoc;
+use rusqlite::{Connection, Transaction};
+use std::cmp::Ordering;
+
+type Step = fn(&Transaction) -> Result<()>;
+
+/// Step `i` upgrades the schema from version `i` to `i + 1`, recorded in
+/// `PRAGMA user_version`. Append new steps, never edit released ones.
+const MIGRATIONS: &[(&str, Step)] = &[
+    ("initial schema", initial),
+    ("repository settings", repository_meta),
+    ("line counts of changes", change_lines),
+];
+
+/// Schema version this binary reads and writes
+pub const VERSION: i64 = MIGRATIONS.len() as i64;
+
+pub fn version(conn: &Connection) -> Result<i64> {
+    let version = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    Ok(version)
+}
+
+/// Refuse databases this binary does not understand
+pub fn check(conn: &Connection) -> Result<()> {
+    let version = version(conn)?;
+    match version.cmp(&VERSION) {
+        Ordering::Equal => Ok(()),
+        Ordering::Less => Err(anyhow!(
+            "rogit.db schema v{} is older than v{}, run `rogit --migrate`",
+            version,
+            VERSION
+        )),
+        Ordering::Greater => Err(anyhow!(
+            "rogit.db schema v{} is newer than v{}, upgrade rogit",
+            version,
+            VERSION
+        )),
+    }
+}
+
+/// Apply pending steps in order, each in its own transaction. Must run
+/// before foreign_keys is on, table rebuild would cascade.
+pub fn migrate(conn: &Connection) -> Result<u32> {
+    let version = version(conn)?;
+    if version > VERSION {
+        check(conn)?;
+    }
+    let mut count = 0;
+    for (i, (name, step)) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        println!("[database] migrate: v{} {}", i + 1, name);
+        let tx = conn.unchecked_transaction()?;
+        step(&tx)?;
+        tx.pragma_update(None, "user_version", i as i64 + 1)?;
+        tx.commit()?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Create every table, upgrading databases from before versioning first
+fn initial(tx: &Transaction) -> Result<()> {
+    migrate_editfiles(tx)?;
+    add_column(tx, "repositories", "size", "INTEGER")?;
+    add_column(tx, "repositories", "error", "TEXT")?;
+
+    tx.execute_batch(indoc! {r#"
+        CREATE TABLE IF NOT EXISTS "repositories" (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            name    TEXT NOT NULL UNIQUE,
+            show    TEXT,
+            head    TEXT NOT NULL,
+            size    INTEGER,            -- bytes on disk
+            error   TEXT,               -- reason of the last failed update
+            fake    INTEGER DEFAULT 0   -- used to delete expired
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS contributors (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            name    TEXT NOT NULL,
+            mail    TEXT NOT NULL,
+            UNIQUE(name, mail)
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS "messages" (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            repo    INTEGER NOT NULL,
+            author  INTEGER NOT NULL,
+            msg     TEXT,
+            date    TEXT,               -- same as date in commit
+            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
+            FOREIGN KEY(author) REFERENCES contributors(id)
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS "commits" (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            hash    TEXT NOT NULL,
+            repo    INTEGER NOT NULL,
+            tree    TEXT NOT NULL,
+            date    TEXT,               -- commit date
+            c7r_id  INTEGER NOT NULL,   -- committer
+            msg_id  INTEGER NOT NULL,   -- think author write it
+            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
+            FOREIGN KEY(c7r_id) REFERENCES contributors(id),
+            FOREIGN KEY(msg_id) REFERENCES messages(id),
+            UNIQUE(repo, hash)
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS "relations" (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            repo    INTEGER NOT NULL,
+            child   INTEGER NOT NULL,
+            parent  INTEGER NOT NULL,
+            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
+            FOREIGN KEY(child) REFERENCES commits(id) ON DELETE CASCADE,
+            FOREIGN KEY(parent) REFERENCES commits(id) ON DELETE CASCADE,
+            UNIQUE(repo, child, parent)
+        ) STRICT;
+
+        CREATE INDEX IF NOT EXISTS idx_relations_child ON relations(child);
+        CREATE INDEX IF NOT EXISTS idx_relations_parent ON relations(parent);
+
+        CREATE TABLE IF NOT EXISTS "editfiles" (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            repo    INTEGER NOT NULL,
+            path    TEXT NOT NULL,
+            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
+            UNIQUE(repo, path)  -- also serves prefix range scans
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS "changes" (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            diff    INTEGER NOT NULL,
+            mode    INTEGER NOT NULL,
+            file    INTEGER NOT NULL,
+            FOREIGN KEY(diff) REFERENCES relations(id) ON DELETE CASCADE
+            FOREIGN KEY(file) REFERENCES editfiles(id) ON DELETE CASCADE
+        ) STRICT;
+
+        CREATE INDEX IF NOT EXISTS idx_changes_diff ON changes(diff);
+        CREATE INDEX IF NOT EXISTS idx_changes_file ON changes(file);
+
+        CREATE TABLE IF NOT EXISTS "branches" (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            name    TEXT NOT NULL,
+            hash    INTEGER NOT NULL,   -- target commit
+            repo    INTEGER NOT NULL,
+            fake    INTEGER DEFAULT 0,
+            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
+            FOREIGN KEY(hash) REFERENCES commits(id),
+            UNIQUE(repo, name)
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS "tags" (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            name    TEXT NOT NULL,
+            hash    INTEGER NOT NULL,   -- target commit
+            repo    INTEGER NOT NULL,
+            msg_id  INTEGER,            -- only tag object has
+            fake    INTEGER DEFAULT 0,
+            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
+            FOREIGN KEY(hash) REFERENCES commits(id),
+            FOREIGN KEY(msg_id) REFERENCES messages(id),
+            UNIQUE(repo, name)
+        ) STRICT;
+
+        CREATE TABLE IF NOT EXISTS "codefiles" (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            repo    INTEGER NOT NULL,
+            path    TEXT NOT NULL,
+            blob    TEXT NOT NULL,      -- skip unchanged files
+            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
+            UNIQUE(repo, path)
+        ) STRICT;
+
+        -- rowid is codefiles.id, only text files of head tip
+        CREATE VIRTUAL TABLE IF NOT EXISTS "codesearch" USING fts5(
+            content,
+            tokenize = 'trigram'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS "codefiles_delete"
+        AFTER DELETE ON "codefiles" BEGIN
+            DELETE FROM "codesearch" WHERE rowid = old.id;
+        END;
+
+        CREATE TABLE IF NOT EXISTS "activities" (
+            repo    INTEGER NOT NULL,
+            author  INTEGER NOT NULL,
+            day     TEXT NOT NULL,      -- author local date
+            hour    INTEGER NOT NULL,   -- author local hour
+            commits INTEGER NOT NULL,
+            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
+            FOREIGN KEY(author) REFERENCES contributors(id),
+            PRIMARY KEY(repo, author, day, hour)
+        ) STRICT;
+
+        -- incremental update hides history of these
+        CREATE TABLE IF NOT EXISTS "reftips" (
+            repo    INTEGER NOT NULL,
+            name    TEXT NOT NULL,      -- full ref name
+            hash    TEXT NOT NULL,      -- peeled commit
+            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
+            PRIMARY KEY(repo, name)
+        ) STRICT;
+
+    "#})?;
+    Ok(())
+}
+
+/// Rebuild the global `editfiles` of old databases into per-repository
+/// rows, remapping `changes.file` through `relations.repo`.
+fn migrate_editfiles(conn: &Connection) -> Result<()> {
+    let columns = table_columns(conn, "editfiles")?;
+    if columns.is_empty() || columns.iter().any(|c| c == "repo") {
+        return Ok(());
+    }
+    println!("[database] migrate: editfiles per repository");
+    conn.execute_batch(indoc! {r#"
+        CREATE TABLE "editfiles_new" (
+            id      INTEGER PRIMARY KEY AUTOINCREMENT,
+            repo    INTEGER NOT NULL,
+            path    TEXT NOT NULL,
+            FOREIGN KEY(repo) REFERENCES repositories(id) ON DELETE CASCADE,
+            UNIQUE(repo, path)
+        ) STRICT;
+
+        INSERT INTO "editfiles_new" (repo, path)
+        SELECT DISTINCT r.repo, e.path
+        FROM "changes" c
+        JOIN "relations" r ON r.id = c.diff
+        JOIN "editfiles" e ON e.id = c.file;
+
+        UPDATE "changes" SET file = (
+            SELECT n.id FROM "editfiles_new" n
+            JOIN "relations" r ON r.id = "changes".diff
+            JOIN "editfiles" e ON e.id = "changes".file
+            WHERE n.repo = r.repo AND n.path = e.path
+        );
+
+        DROP TABLE "editfiles";
+        ALTER TABLE "editfiles_new" RENAME TO "editfiles";
+    "#})?;
+    Ok(())
+}
+
+/// Display name, category and visibility from the config file
+fn repository_meta(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(indoc! { r#"
+        ALTER TABLE "repositories" ADD COLUMN "display_name" TEXT;
+        ALTER TABLE "repositories" ADD COLUMN "category" TEXT;
+        ALTER TABLE "repositories" ADD COLUMN "hidden" INTEGER NOT NULL DEFAULT 0;
+        "# })?;
+    Ok(())
+}
+
+/// Lines added and removed per changed file, NULL for rows of older
+/// updates and merges
+fn change_lines(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(indoc! { r#"
+        ALTER TABLE "changes" ADD COLUMN "added" INTEGER;
+        ALTER TABLE "changes" ADD COLUMN "removed" INTEGER;
+        "# })?;
+    Ok(())
+}
+
+/// Empty if the table does not exist
+fn table_columns(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let columns = conn
+        .prepare(r#"SELECT "name" FROM pragma_table_info(?1)"#)?
+        .query_map([table], |r| r.get::<_, String>(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(columns)
+}
+
+/// Add a column missing in old databases, the table is created later
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let columns = table_columns(conn, table)?;
+    if columns.is_empty() || columns.iter().any(|c| c == column) {
+        return Ok(());
+    }
+    println!("[database] migrate: {}.{}", table, column);
+    conn.execute_batch(&format!(
+        r#"ALTER TABLE "{}" ADD COLUMN "{}" {};"#,
+        table, column, decl
+    ))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_new_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate(&conn).unwrap(), VERSION as u32);
+        assert_eq!(version(&conn).unwrap(), VERSION);
+        check(&conn).unwrap();
+        // nothing left to apply
+        assert_eq!(migrate(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn migrate_step_by_step() {
+        let conn = Connection::open_in_memory().unwrap();
+        let tx = conn.unchecked_transaction().unwrap();
+        initial(&tx).unwrap();
+        tx.pragma_update(None, "user_version", 1).unwrap();
+        tx.commit().unwrap();
+        assert!(check(&conn).is_err());
+        assert!(!table_columns(&conn, "changes")
+            .unwrap()
+            .contains(&"added".into()));
+
+        assert_eq!(migrate(&conn).unwrap(), VERSION as u32 - 1);
+        let repositories = table_columns(&conn, "repositories").unwrap();
+        for column in ["display_name", "category", "hidden"] {
+            assert!(repositories.contains(&column.into()), "{}", column);
+        }
+        let changes = table_columns(&conn, "changes").unwrap();
+        for column in ["added", "removed"] {
+            assert!(changes.contains(&column.into()), "{}", column);
+        }
+    }
+
+    #[test]
+    fn refuse_newer_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", VERSION + 1)
+            .unwrap();
+        assert!(check(&conn).is_err());
+        assert!(migrate(&conn).is_err());
+    }
+}
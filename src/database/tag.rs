@@ -1,131 +1,102 @@
+use super::{contributor::Person, message};
 use anyhow::Result;
-use core::str;
-use git2::Object;
-use log::debug;
-use rusqlite::{named_params, OptionalExtension, Transaction};
-
-use crate::database::person;
-
-const SQL_INIT_CREATE: &str = r#"
-CREATE TABLE IF NOT EXISTS "tag" (
-    "name"      TEXT PRIMARY KEY,
-    "target_id" TEXT NOT NULL,
-    "is_lw"     INTEGER DEFAULT 0,
-    "message"   TEXT,
-    "tagger"    INTEGER
-) WITHOUT ROWID;
-
-CREATE TEMPORARY TABLE "temp_tag" AS
-SELECT
-    "name"
-FROM
-    "tag";
-"#;
-
-pub fn init(tx: &mut Transaction) -> Result<()> {
-    tx.execute_batch(SQL_INIT_CREATE)?;
-    Ok(())
+use indoc::indoc;
+use rusqlite::{named_params, Connection, OptionalExtension, Transaction};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct Tag {
+    pub repo: String,
+    pub name: String,
+    pub hash: String,
+    pub date: String,
+    pub author: String,
+    pub mail: String,
+    /// tag message of annotated tag, commit message otherwise
+    pub msg: String,
+    pub annotated: bool,
 }
 
-const SQL_TARGET_SELECT: &str = r#"
-SELECT "target_id" FROM "tag" WHERE "name" = :name LIMIT 1;
-"#;
-
-const SQL_TARGET_DELETE: &str = r#"
-DELETE FROM "temp_tag" WHERE "name" = :name;
-"#;
-
-pub fn target(tx: &mut Transaction, name: &str) -> Result<Option<String>> {
-    let sp = tx.savepoint()?;
-
-    let mut stmt_delete = sp.prepare_cached(SQL_TARGET_DELETE)?;
-    stmt_delete.execute(named_params! {":name": name})?;
-    drop(stmt_delete);
-
-    let mut stmt_select = sp.prepare_cached(SQL_TARGET_SELECT)?;
-    let commit = stmt_select
-        .query_row(named_params! {":name": name}, |r| r.get::<_, String>(0))
+/// Target commit id of the tag, also marks it as alive
+pub fn get_commit(tx: &Transaction, name: &str, repo_id: i64) -> Result<Option<i64>> {
+    let mut stmt = tx.prepare_cached(indoc! { r#"
+        UPDATE "tags" SET fake = 0
+        WHERE "repo" = :repo AND "name" = :name
+        RETURNING "hash"
+        "# })?;
+    let id = stmt
+        .query_row(named_params! {":repo": repo_id, ":name": name}, |r| {
+            r.get::<_, i64>(0)
+        })
         .optional()?;
-    drop(stmt_select);
-
-    sp.commit()?;
-    Ok(commit)
+    Ok(id)
 }
 
-const SQL_UPSERT: &str = r#"
-INSERT INTO
-    "tag" (
-        "name",
-        "target_id",
-        "is_lw",
-        "message",
-        "tagger"
-    )
-VALUES
-    (:name, :target_id, :is_lw, :message, :tagger) ON CONFLICT("name") DO
-UPDATE
-SET
-    "target_id" = :target_id,
-    "is_lw" = :is_lw,
-    "message" = :message,
-    "tagger" = :tagger;
-"#;
-
-pub fn upsert(tx: &mut Transaction, name: &str, target_obj: &Object) -> Result<()> {
-    debug!(target: "tag", "insert {}", name);
-
-    let mut sp = tx.savepoint()?;
-    let mut is_lw = true;
-    let mut message = "";
-    let mut tagger = 0;
-    if let Some(tag) = target_obj.as_tag() {
-        is_lw = false;
-        message = tag.message().unwrap_or_default();
-        if let Some(s) = tag.tagger() {
-            tagger = person::sync(&mut sp, &s)?
-        }
-    } else if let Some(commit) = target_obj.as_commit() {
-        message = commit.message().unwrap_or_default();
-        tagger = person::sync(&mut sp, &commit.committer())?;
-    } else {
-        // tag on tree?
-    }
-
-    let mut stmt = sp.prepare_cached(SQL_UPSERT)?;
+/// `annotation` is the message and tagger of an annotated tag
+pub fn upsert(
+    tx: &Transaction,
+    name: &str,
+    commit_id: i64,
+    annotation: Option<(&str, &Person)>,
+    repo_id: i64,
+) -> Result<()> {
+    let msg_id = match annotation {
+        Some((msg, sig)) => Some(message::insert(tx, msg, sig, repo_id)?),
+        None => None,
+    };
+    let mut stmt = tx.prepare_cached(indoc! { r#"
+        INSERT INTO "tags" (name, hash, repo, msg_id, fake)
+        VALUES (:name, :hash, :repo, :msg_id, 0)
+        ON CONFLICT(repo, name)
+        DO UPDATE SET
+            hash = EXCLUDED.hash,
+            msg_id = EXCLUDED.msg_id,
+            fake = 0;
+        "# })?;
     stmt.execute(named_params! {
         ":name": name,
-        ":target_id": target_obj.id().to_string(),
-        ":is_lw": is_lw,
-        ":message": message,
-        ":tagger": tagger,
+        ":hash": commit_id,
+        ":repo": repo_id,
+        ":msg_id": msg_id,
     })?;
-
-    drop(stmt);
-
-    sp.commit()?;
     Ok(())
 }
 
-const SQL_CLEAR: &str = r#"
-DELETE FROM
-    "tag"
-WHERE
-    "name" IN (
-        SELECT
-            "name"
-        FROM
-            "temp_tag"
-    );
-"#;
-
-pub fn clear(tx: &mut Transaction) -> Result<()> {
-    let sp = tx.savepoint()?;
-    let mut stmt = sp.prepare_cached(SQL_CLEAR)?;
-    let count = stmt.execute(())?;
-
-    drop(stmt);
-    sp.commit()?;
-
-    debug!(target: "tag", "remove {}", count);
-    Ok(())
+/// Latest tags by tagger date, or commit date of lightweight tags
+pub fn latest(
+    conn: &Connection,
+    repo_id: Option<i64>,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<Tag>> {
+    let mut stmt = conn.prepare_cached(indoc! { r#"
+        SELECT r.name, t.name, c.hash,
+            strftime('%Y-%m-%dT%H:%M:%SZ', unixepoch(COALESCE(tm.date, c.date)), 'unixepoch'),
+            p.name, p.mail, COALESCE(tm.msg, cm.msg), t.msg_id IS NOT NULL
+        FROM "tags" t
+        JOIN "repositories" r ON r.id = t.repo
+        JOIN "commits" c ON c.id = t.hash
+        JOIN "messages" cm ON cm.id = c.msg_id
+        LEFT JOIN "messages" tm ON tm.id = t.msg_id
+        JOIN "contributors" p ON p.id = COALESCE(tm.author, cm.author)
+        WHERE (:repo IS NULL AND NOT r.hidden OR t.repo = :repo)
+        ORDER BY unixepoch(COALESCE(tm.date, c.date)) DESC
+        LIMIT :limit OFFSET :offset
+        "# })?;
+    let params = named_params! {":repo": repo_id, ":limit": limit, ":offset": offset};
+    let rows = stmt
+        .query_map(params, |r| {
+            Ok(Tag {
+                repo: r.get(0)?,
+                name: r.get(1)?,
+                hash: r.get(2)?,
+                date: r.get(3)?,
+                author: r.get(4)?,
+                mail: r.get(5)?,
+                msg: r.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                annotated: r.get(7)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
 }
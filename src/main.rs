@@ -1,19 +1,37 @@
 mod config;
 mod database;
+mod server;
 mod update;
 use config::Mode;
 
 fn main() {
-    let mut c = config::Config::new().unwrap();
+    let mut c = match config::Config::new() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
     c.scan();
     println!("{c}");
+    if c.fetch {
+        if let Err(e) = update::fetch::set_timeout(c.fetch_timeout) {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    }
 
     let r = match c.mode {
         Mode::Update => update::run(&c),
-        Mode::Server => update::run(&c),
+        Mode::Server => server::run(&c),
+        Mode::Migrate => c.migrate_db(),
+        Mode::Check => c.check_db(),
     };
     match r {
         Ok(_) => println!("Done!"),
-        Err(e) => eprintln!("{}", e),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
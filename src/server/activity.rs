@@ -0,0 +1,105 @@
+use super::{find_repo, repo_id, with_db, AppError, Shared};
+use crate::database::{self, activity::Bucket};
+use axum::{
+    debug_handler,
+    extract::{Path, Query, State},
+};
+use serde::Deserialize;
+use std::fmt::Write;
+
+#[derive(Deserialize)]
+pub struct ActivityQuery {
+    #[serde(default)]
+    by: Bucket,
+    /// contributor id, only useful inside a repository
+    author: Option<i64>,
+}
+
+#[debug_handler]
+pub async fn repo_series(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    Query(query): Query<ActivityQuery>,
+) -> Result<String, AppError> {
+    find_repo(&c, &repo)?;
+    series(&c, Some(repo), query.author, query.by).await
+}
+
+#[debug_handler]
+pub async fn repo_punchcard(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    Query(query): Query<ActivityQuery>,
+) -> Result<String, AppError> {
+    find_repo(&c, &repo)?;
+    punchcard(&c, Some(repo), query.author).await
+}
+
+#[debug_handler]
+pub async fn author_series(
+    Path(id): Path<i64>,
+    State(c): State<Shared>,
+    Query(query): Query<ActivityQuery>,
+) -> Result<String, AppError> {
+    series(&c, None, Some(id), query.by).await
+}
+
+#[debug_handler]
+pub async fn author_punchcard(
+    Path(id): Path<i64>,
+    State(c): State<Shared>,
+) -> Result<String, AppError> {
+    punchcard(&c, None, Some(id)).await
+}
+
+async fn series(
+    c: &Shared,
+    repo: Option<String>,
+    author: Option<i64>,
+    by: Bucket,
+) -> Result<String, AppError> {
+    let rows = with_db(c, move |conn| {
+        let repo_id = match repo {
+            Some(name) => Some(repo_id(conn, &name)?),
+            None => None,
+        };
+        Ok(database::activity::series(conn, repo_id, author, by)?)
+    })
+    .await?;
+
+    let mut out = String::new();
+    for (bucket, count) in rows {
+        writeln!(out, "{}\t{}", bucket, count)?;
+    }
+    Ok(out)
+}
+
+async fn punchcard(
+    c: &Shared,
+    repo: Option<String>,
+    author: Option<i64>,
+) -> Result<String, AppError> {
+    let rows = with_db(c, move |conn| {
+        let repo_id = match repo {
+            Some(name) => Some(repo_id(conn, &name)?),
+            None => None,
+        };
+        Ok(database::activity::punchcard(conn, repo_id, author)?)
+    })
+    .await?;
+
+    // one line per weekday, 24 hourly counts
+    let mut grid = [[0; 24]; 7];
+    for (weekday, hour, count) in rows {
+        grid[weekday as usize % 7][hour as usize % 24] = count;
+    }
+    let mut out = String::new();
+    for (weekday, hours) in ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"]
+        .iter()
+        .zip(grid)
+    {
+        let hours: Vec<String> = hours.iter().map(|n| n.to_string()).collect();
+        writeln!(out, "{}\t{}", weekday, hours.join("\t"))?;
+    }
+    Ok(out)
+}
@@ -0,0 +1,403 @@
+use super::{find_repo, html, repo_id, repo_row, with_db, with_repo, AppError, Shared};
+use crate::database::{
+    self,
+    branch::Branch,
+    change::Change,
+    commit::Log,
+    contributor::Authored,
+    contributor::Ranked,
+    repository::{Overview, Sort},
+    tag::Tag,
+};
+use axum::{
+    debug_handler,
+    extract::{
+        rejection::{PathRejection, QueryRejection},
+        OriginalUri, Path, Query, State,
+    },
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use git2::Signature;
+use serde::{Deserialize, Serialize};
+
+const PAGE_LIMIT: u32 = 50;
+const PAGE_LIMIT_MAX: u32 = 200;
+
+/// `{"error": {"status": 404, "message": "..."}}` for every failure
+pub struct ApiError(AppError);
+
+impl From<AppError> for ApiError {
+    fn from(e: AppError) -> Self {
+        ApiError(e)
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(e: QueryRejection) -> Self {
+        ApiError(AppError::BadRequest(e.body_text()))
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(e: PathRejection) -> Self {
+        ApiError(AppError::BadRequest(e.body_text()))
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail {
+    status: u16,
+    message: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self.0 {
+            AppError::NotFound(m) => (StatusCode::NOT_FOUND, m),
+            AppError::BadRequest(m) => (StatusCode::BAD_REQUEST, m),
+            AppError::Internal(e) => {
+                eprintln!("[server] error: {:#}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal error".into())
+            }
+        };
+        let body = ErrorBody {
+            error: ErrorDetail {
+                status: status.as_u16(),
+                message,
+            },
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+pub async fn fallback() -> ApiError {
+    ApiError(AppError::NotFound("no such endpoint".into()))
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    #[serde(default)]
+    offset: u32,
+    limit: Option<u32>,
+}
+
+impl PageQuery {
+    fn limit(&self) -> u32 {
+        self.limit.unwrap_or(PAGE_LIMIT).clamp(1, PAGE_LIMIT_MAX)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Page<T> {
+    data: Vec<T>,
+    links: Links,
+}
+
+#[derive(Serialize)]
+pub struct Links {
+    prev: Option<String>,
+    next: Option<String>,
+}
+
+/// `data` is fetched with one extra row to tell whether a next page exists
+fn paginate<T>(uri: &Uri, page: &PageQuery, mut data: Vec<T>) -> Page<T> {
+    let limit = page.limit();
+    let more = data.len() > limit as usize;
+    data.truncate(limit as usize);
+    let link = |offset: u32| {
+        // keep other parameters, such as `ref`
+        let mut query: Vec<&str> = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|p| !p.is_empty() && !p.starts_with("offset=") && !p.starts_with("limit="))
+            .collect();
+        let paging = format!("offset={}&limit={}", offset, limit);
+        query.push(&paging);
+        format!("{}?{}", uri.path(), query.join("&"))
+    };
+    Page {
+        data,
+        links: Links {
+            prev: (page.offset > 0).then(|| link(page.offset.saturating_sub(limit))),
+            next: more.then(|| link(page.offset + limit)),
+        },
+    }
+}
+
+#[debug_handler]
+pub async fn repositories(
+    State(c): State<Shared>,
+    OriginalUri(uri): OriginalUri,
+    query: Result<Query<html::IndexQuery>, QueryRejection>,
+    page: Result<Query<PageQuery>, QueryRejection>,
+) -> ApiResult<Page<Overview>> {
+    let (Query(query), Query(page)) = (query?, page?);
+    let repos = with_db(&c, move |conn| {
+        let filter = query.q.as_deref().map(str::trim).filter(|f| !f.is_empty());
+        Ok(database::repository::overview(
+            conn, None, filter, query.sort,
+        )?)
+    })
+    .await?;
+    let repos = repos
+        .into_iter()
+        .skip(page.offset as usize)
+        .take(page.limit() as usize + 1)
+        .collect();
+    Ok(Json(paginate(&uri, &page, repos)))
+}
+
+#[debug_handler]
+pub async fn repository(
+    State(c): State<Shared>,
+    repo: Result<Path<String>, PathRejection>,
+) -> ApiResult<Overview> {
+    let Path(repo) = repo?;
+    find_repo(&c, &repo)?;
+    let r = with_db(&c, move |conn| {
+        let mut rows = database::repository::overview(conn, Some(&repo), None, Sort::Name)?;
+        rows.pop()
+            .ok_or_else(|| AppError::NotFound(format!("repository not found: {}", repo)))
+    })
+    .await?;
+    Ok(Json(r))
+}
+
+#[debug_handler]
+pub async fn branches(
+    State(c): State<Shared>,
+    repo: Result<Path<String>, PathRejection>,
+) -> ApiResult<Vec<Branch>> {
+    let Path(repo) = repo?;
+    find_repo(&c, &repo)?;
+    let branches = with_db(&c, move |conn| {
+        let repo_id = repo_id(conn, &repo)?;
+        Ok(database::branch::list(conn, repo_id)?)
+    })
+    .await?;
+    Ok(Json(branches))
+}
+
+#[debug_handler]
+pub async fn tags(
+    State(c): State<Shared>,
+    OriginalUri(uri): OriginalUri,
+    repo: Result<Path<String>, PathRejection>,
+    page: Result<Query<PageQuery>, QueryRejection>,
+) -> ApiResult<Page<Tag>> {
+    let (Path(repo), Query(page)) = (repo?, page?);
+    find_repo(&c, &repo)?;
+    let (limit, offset) = (page.limit() + 1, page.offset);
+    let tags = with_db(&c, move |conn| {
+        let repo_id = repo_id(conn, &repo)?;
+        Ok(database::tag::latest(conn, Some(repo_id), limit, offset)?)
+    })
+    .await?;
+    Ok(Json(paginate(&uri, &page, tags)))
+}
+
+#[derive(Serialize)]
+pub struct Refs {
+    branches: Vec<Branch>,
+    tags: Vec<Tag>,
+}
+
+/// Branches and all tags at once, `/branches` and `/tags` page the same data
+#[debug_handler]
+pub async fn refs(
+    State(c): State<Shared>,
+    repo: Result<Path<String>, PathRejection>,
+) -> ApiResult<Refs> {
+    let Path(repo) = repo?;
+    find_repo(&c, &repo)?;
+    let refs = with_db(&c, move |conn| {
+        let repo_id = repo_id(conn, &repo)?;
+        let branches = database::branch::list(conn, repo_id)?;
+        let tags = database::tag::latest(conn, Some(repo_id), u32::MAX, 0)?;
+        Ok(Refs { branches, tags })
+    })
+    .await?;
+    Ok(Json(refs))
+}
+
+#[derive(Deserialize)]
+pub struct LogQuery {
+    #[serde(rename = "ref")]
+    refname: Option<String>,
+    path: Option<String>,
+}
+
+#[debug_handler]
+pub async fn log(
+    State(c): State<Shared>,
+    OriginalUri(uri): OriginalUri,
+    repo: Result<Path<String>, PathRejection>,
+    query: Result<Query<LogQuery>, QueryRejection>,
+    page: Result<Query<PageQuery>, QueryRejection>,
+) -> ApiResult<Page<Log>> {
+    let (Path(repo), Query(query), Query(page)) = (repo?, query?, page?);
+    let (limit, offset) = (page.limit() + 1, page.offset);
+    let name = repo.clone();
+    let logs = with_repo(&c, &repo, move |conn, git| {
+        let r = repo_row(conn, &name)?;
+        let refname = query.refname.unwrap_or(r.head);
+        let start = html::rev_commit(git, &refname)?;
+        let path = query.path.as_deref();
+        let logs = database::commit::log(conn, r.id, &start, path, limit, offset)?;
+        Ok(logs)
+    })
+    .await?;
+    Ok(Json(paginate(&uri, &page, logs)))
+}
+
+#[derive(Serialize)]
+pub struct Person {
+    name: String,
+    email: String,
+    date: String,
+}
+
+impl Person {
+    fn new(sig: &Signature) -> Result<Self, AppError> {
+        Ok(Person {
+            name: sig.name().unwrap_or_default().to_owned(),
+            email: sig.email().unwrap_or_default().to_owned(),
+            date: database::datetime::to_iso(sig.when())?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct Commit {
+    hash: String,
+    tree: String,
+    parents: Vec<String>,
+    author: Person,
+    committer: Person,
+    message: String,
+}
+
+#[debug_handler]
+pub async fn commit(
+    State(c): State<Shared>,
+    path: Result<Path<(String, String)>, PathRejection>,
+) -> ApiResult<Commit> {
+    let Path((repo, hash)) = path?;
+    let commit = with_repo(&c, &repo, move |_, git| {
+        let hash = html::rev_commit(git, &hash)?;
+        let commit = git.find_commit(git2::Oid::from_str(&hash)?)?;
+        let author = Person::new(&commit.author())?;
+        let committer = Person::new(&commit.committer())?;
+        Ok(Commit {
+            hash: commit.id().to_string(),
+            tree: commit.tree_id().to_string(),
+            parents: commit.parent_ids().map(|p| p.to_string()).collect(),
+            author,
+            committer,
+            message: commit.message().unwrap_or_default().to_owned(),
+        })
+    })
+    .await?;
+    Ok(Json(commit))
+}
+
+#[debug_handler]
+pub async fn changes(
+    State(c): State<Shared>,
+    path: Result<Path<(String, String)>, PathRejection>,
+) -> ApiResult<Vec<Change>> {
+    let Path((repo, hash)) = path?;
+    let name = repo.clone();
+    let changes = with_repo(&c, &repo, move |conn, git| {
+        let hash = html::rev_commit(git, &hash)?;
+        let repo_id = repo_id(conn, &name)?;
+        let changes = database::change::list(conn, &hash, repo_id)?;
+        if changes.is_empty() && database::commit::get_id_by_hash(conn, &hash, repo_id)?.is_none() {
+            return Err(AppError::NotFound(format!("commit not found: {}", hash)));
+        }
+        Ok(changes)
+    })
+    .await?;
+    Ok(Json(changes))
+}
+
+#[derive(Deserialize)]
+pub struct TreeQuery {
+    id: Option<String>,
+    #[serde(default)]
+    path: String,
+}
+
+#[debug_handler]
+pub async fn tree(
+    State(c): State<Shared>,
+    repo: Result<Path<String>, PathRejection>,
+    query: Result<Query<TreeQuery>, QueryRejection>,
+) -> ApiResult<Vec<html::TreeItem>> {
+    let (Path(repo), Query(query)) = (repo?, query?);
+    let dir = query.path.trim_matches('/').to_owned();
+    let name = repo.clone();
+    let entries = with_repo(&c, &repo, move |conn, git| {
+        let rev = html::rev_or_head(conn, &name, query.id)?;
+        html::list_tree(git, &rev, &dir)
+    })
+    .await?;
+    Ok(Json(entries))
+}
+
+#[debug_handler]
+pub async fn contributors(
+    State(c): State<Shared>,
+    repo: Result<Path<String>, PathRejection>,
+) -> ApiResult<Vec<Ranked>> {
+    let Path(repo) = repo?;
+    find_repo(&c, &repo)?;
+    let ranked = with_db(&c, move |conn| {
+        let repo_id = repo_id(conn, &repo)?;
+        Ok(database::contributor::rank(conn, repo_id)?)
+    })
+    .await?;
+    Ok(Json(ranked))
+}
+
+#[derive(Serialize)]
+pub struct Contributor {
+    id: i64,
+    name: String,
+    mail: String,
+    commits: Page<Authored>,
+}
+
+#[debug_handler]
+pub async fn contributor(
+    State(c): State<Shared>,
+    OriginalUri(uri): OriginalUri,
+    id: Result<Path<i64>, PathRejection>,
+    page: Result<Query<PageQuery>, QueryRejection>,
+) -> ApiResult<Contributor> {
+    let (Path(id), Query(page)) = (id?, page?);
+    let (limit, offset) = (page.limit() + 1, page.offset);
+    let ((name, mail), commits) = with_db(&c, move |conn| {
+        let sig = database::contributor::get(conn, id)?
+            .ok_or_else(|| AppError::NotFound(format!("contributor not found: {}", id)))?;
+        let commits = database::contributor::commits(conn, id, limit, offset)?;
+        Ok((sig, commits))
+    })
+    .await?;
+    Ok(Json(Contributor {
+        id,
+        name,
+        mail,
+        commits: paginate(&uri, &page, commits),
+    }))
+}
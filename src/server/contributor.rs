@@ -0,0 +1,52 @@
+use super::{find_repo, repo_id, with_db, AppError, Shared};
+use crate::database;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+};
+use std::fmt::Write;
+
+const CONTRIBUTOR_COMMIT_LIMIT: u32 = 200;
+
+#[debug_handler]
+pub async fn list(Path(repo): Path<String>, State(c): State<Shared>) -> Result<String, AppError> {
+    find_repo(&c, &repo)?;
+    let ranked = with_db(&c, move |conn| {
+        let repo_id = repo_id(conn, &repo)?;
+        Ok(database::contributor::rank(conn, repo_id)?)
+    })
+    .await?;
+
+    let mut out = String::new();
+    for p in ranked {
+        let lines = match (p.added, p.removed) {
+            (Some(added), Some(removed)) => format!("\tlines: +{} -{}", added, removed),
+            _ => String::new(),
+        };
+        writeln!(
+            out,
+            "{}\t{} <{}>\tcommits: {}\tfiles: {}{}\t{} ~ {}",
+            p.id, p.name, p.mail, p.commits, p.files, lines, p.first, p.last
+        )?;
+    }
+    Ok(out)
+}
+
+#[debug_handler]
+pub async fn show(Path(id): Path<i64>, State(c): State<Shared>) -> Result<String, AppError> {
+    let (sig, commits) = with_db(&c, move |conn| {
+        let sig = database::contributor::get(conn, id)?
+            .ok_or_else(|| AppError::NotFound(format!("contributor not found: {}", id)))?;
+        let commits = database::contributor::commits(conn, id, CONTRIBUTOR_COMMIT_LIMIT, 0)?;
+        Ok((sig, commits))
+    })
+    .await?;
+
+    let mut out = String::new();
+    writeln!(out, "{} <{}>", sig.0, sig.1)?;
+    for a in commits {
+        let summary = a.msg.lines().next().unwrap_or_default();
+        writeln!(out, "{}\t{:.7}\t{}\t{}", a.repo, a.hash, a.date, summary)?;
+    }
+    Ok(out)
+}
@@ -0,0 +1,151 @@
+use super::{smart::find_git_repo, AppError, Shared};
+use anyhow::anyhow;
+use axum::{
+    body::Body,
+    debug_handler,
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use std::{fmt::Write, path::PathBuf};
+use tokio_util::io::ReaderStream;
+
+/// Objects never change once written
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// `info/refs` as written by `git update-server-info`, built from the refs
+/// when the file is missing
+pub async fn info_refs(path: PathBuf) -> Result<Response, AppError> {
+    let file = path.join("info/refs");
+    let body = match tokio::fs::read(&file).await {
+        Ok(body) => body,
+        Err(_) => tokio::task::spawn_blocking(move || gen_info_refs(path))
+            .await
+            .map_err(|e| anyhow!(e))??
+            .into_bytes(),
+    };
+    Ok(text(body))
+}
+
+#[debug_handler]
+pub async fn head(Path(repo): Path<String>, State(c): State<Shared>) -> Result<Response, AppError> {
+    let path = find_git_repo(&c, &repo)?.path.join("HEAD");
+    Ok(text(tokio::fs::read(path).await?))
+}
+
+/// Like `objects/info/packs` from `git update-server-info`, built from the
+/// pack directory when the file is missing
+#[debug_handler]
+pub async fn packs(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+) -> Result<Response, AppError> {
+    let path = find_git_repo(&c, &repo)?.path.join("objects");
+    if let Ok(body) = tokio::fs::read(path.join("info/packs")).await {
+        return Ok(text(body));
+    }
+    let mut body = String::new();
+    let mut dir = tokio::fs::read_dir(path.join("pack")).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("pack-") && name.ends_with(".pack") {
+            writeln!(body, "P {}", name)?;
+        }
+    }
+    body.push('\n');
+    Ok(text(body.into_bytes()))
+}
+
+#[debug_handler]
+pub async fn loose(
+    Path((repo, dir, file)): Path<(String, String, String)>,
+    State(c): State<Shared>,
+) -> Result<Response, AppError> {
+    if !(dir.len() == 2 && is_hex(&dir) && file.len() == 38 && is_hex(&file)) {
+        return Err(AppError::NotFound(format!(
+            "not an object: {}/{}",
+            dir, file
+        )));
+    }
+    let path = find_git_repo(&c, &repo)?
+        .path
+        .join("objects")
+        .join(dir)
+        .join(file);
+    send(path, "application/x-git-loose-object").await
+}
+
+#[debug_handler]
+pub async fn pack(
+    Path((repo, file)): Path<(String, String)>,
+    State(c): State<Shared>,
+) -> Result<Response, AppError> {
+    let mime = match file.strip_prefix("pack-").and_then(|f| f.split_once('.')) {
+        Some((hash, "pack")) if is_hex(hash) => "application/x-git-packed-objects",
+        Some((hash, "idx")) if is_hex(hash) => "application/x-git-packed-objects-toc",
+        _ => return Err(AppError::NotFound(format!("not a pack: {}", file))),
+    };
+    let path = find_git_repo(&c, &repo)?
+        .path
+        .join("objects/pack")
+        .join(file);
+    send(path, mime).await
+}
+
+fn gen_info_refs(path: PathBuf) -> Result<String, AppError> {
+    let git = git2::Repository::open_bare(path)?;
+    let mut refs = Vec::new();
+    for r in git.references()? {
+        let r = r?;
+        let (Some(name), Some(oid)) = (r.name(), r.target()) else {
+            continue;
+        };
+        refs.push((name.to_owned(), oid));
+    }
+    refs.sort();
+    let mut body = String::new();
+    for (name, oid) in refs {
+        writeln!(body, "{}\t{}", oid, name)?;
+        let mut peeled = oid;
+        while let Ok(tag) = git.find_tag(peeled) {
+            peeled = tag.target_id();
+        }
+        if peeled != oid {
+            writeln!(body, "{}\t{}^{{}}", peeled, name)?;
+        }
+    }
+    Ok(body)
+}
+
+async fn send(path: PathBuf, mime: &'static str) -> Result<Response, AppError> {
+    let file = tokio::fs::File::open(&path).await.map_err(|_| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        AppError::NotFound(format!("object not found: {}", name))
+    })?;
+    let size = file.metadata().await?.len();
+    Ok((
+        [
+            (header::CONTENT_TYPE, mime.to_owned()),
+            (header::CONTENT_LENGTH, size.to_string()),
+            (header::CACHE_CONTROL, IMMUTABLE.to_owned()),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response())
+}
+
+fn text(body: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+fn is_hex(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_hexdigit())
+}
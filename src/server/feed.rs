@@ -0,0 +1,238 @@
+use super::{encode_path, escape, find_repo, repo_id, repo_row, with_db, AppError, Shared};
+use crate::database;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+};
+use std::fmt::Write;
+
+struct Entry {
+    title: String,
+    link: String,
+    updated: String,
+    author: String,
+    mail: String,
+    content: String,
+}
+
+#[debug_handler]
+pub async fn commits(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    find_repo(&c, &repo)?;
+    let base = base_url(&c, &headers);
+    let name = repo.clone();
+    let limit = c.limits.feed;
+    let (show, logs) = with_db(&c, move |conn| {
+        let r = repo_row(conn, &name)?;
+        let logs = match database::branch::tip(conn, r.id, &r.head)? {
+            Some(tip) => database::commit::log(conn, r.id, &tip, None, limit, 0)?,
+            None => Vec::new(),
+        };
+        Ok((r.show, logs))
+    })
+    .await?;
+    let entries = logs
+        .into_iter()
+        .map(|l| commit_entry(&base, &repo, l))
+        .collect();
+    let title = format!("{} commits", repo);
+    let link = format!("{base}/{}", encode_path(&repo));
+    atom(&title, show.trim(), &link, entries)
+}
+
+#[debug_handler]
+pub async fn tags(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    find_repo(&c, &repo)?;
+    let base = base_url(&c, &headers);
+    let name = repo.clone();
+    let limit = c.limits.feed;
+    let tags = with_db(&c, move |conn| {
+        let repo_id = repo_id(conn, &name)?;
+        Ok(database::tag::latest(conn, Some(repo_id), limit, 0)?)
+    })
+    .await?;
+    let entries = tags.into_iter().map(|t| tag_entry(&base, t)).collect();
+    atom(
+        &format!("{} tags", repo),
+        "",
+        &format!("{base}/{}", encode_path(&repo)),
+        entries,
+    )
+}
+
+#[debug_handler]
+pub async fn branches(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    find_repo(&c, &repo)?;
+    let base = base_url(&c, &headers);
+    let name = repo.clone();
+    let branches = with_db(&c, move |conn| {
+        let repo_id = repo_id(conn, &name)?;
+        Ok(database::branch::list(conn, repo_id)?)
+    })
+    .await?;
+    // a branch moving to a new tip shows up as a new entry
+    let entries = branches
+        .into_iter()
+        .map(|b| Entry {
+            title: format!("{} -> {:.7}", b.name, b.hash),
+            link: format!("{base}/{}/-/commit/{}", encode_path(&repo), b.hash),
+            updated: b.date,
+            author: b.author,
+            mail: b.mail,
+            content: b.msg,
+        })
+        .collect();
+    atom(
+        &format!("{} branches", repo),
+        "",
+        &format!("{base}/{}", encode_path(&repo)),
+        entries,
+    )
+}
+
+/// Head commits and tags of every repository
+#[debug_handler]
+pub async fn all(
+    State(c): State<Shared>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let base = base_url(&c, &headers);
+    let limit = c.limits.feed;
+    let (logs, tags) = with_db(&c, move |conn| {
+        let mut logs = Vec::new();
+        for r in database::repository::list(conn, false)? {
+            let Some(tip) = database::branch::tip(conn, r.id, &r.head)? else {
+                continue;
+            };
+            for l in database::commit::log(conn, r.id, &tip, None, limit, 0)? {
+                logs.push((r.name.clone(), l));
+            }
+        }
+        let tags = database::tag::latest(conn, None, limit, 0)?;
+        Ok((logs, tags))
+    })
+    .await?;
+
+    let mut entries: Vec<Entry> = logs
+        .into_iter()
+        .map(|(repo, l)| commit_entry(&base, &repo, l))
+        .chain(tags.into_iter().map(|t| tag_entry(&base, t)))
+        .collect();
+    // RFC 3339 in UTC sorts as text
+    entries.sort_by(|a, b| b.updated.cmp(&a.updated));
+    entries.truncate(limit as usize);
+    atom(&c.title, "", &base, entries)
+}
+
+fn commit_entry(base: &str, repo: &str, l: database::commit::Log) -> Entry {
+    let summary = l.msg.lines().next().unwrap_or_default();
+    Entry {
+        title: format!("[{}] {}", repo, summary),
+        link: format!("{base}/{}/-/commit/{}", encode_path(repo), l.hash),
+        updated: l.date,
+        author: l.author,
+        mail: l.mail,
+        content: l.msg,
+    }
+}
+
+fn tag_entry(base: &str, t: database::tag::Tag) -> Entry {
+    let kind = if t.annotated {
+        "tag"
+    } else {
+        "lightweight tag"
+    };
+    Entry {
+        title: format!("[{}] {} {}", t.repo, kind, t.name),
+        link: format!("{base}/{}/-/commit/{}", encode_path(&t.repo), t.hash),
+        updated: t.date,
+        author: t.author,
+        mail: t.mail,
+        content: t.msg,
+    }
+}
+
+/// Scheme from `X-Forwarded-Proto` of a reverse proxy, http otherwise
+fn base_url(c: &Shared, headers: &HeaderMap) -> String {
+    let scheme = match headers
+        .get("x-forwarded-proto")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(',').next())
+        .map(str::trim)
+    {
+        Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+        _ => "http",
+    };
+    match headers.get(header::HOST).and_then(|h| h.to_str().ok()) {
+        Some(host) => format!("{}://{}", scheme, host),
+        None => format!("{}://{}:{}", scheme, c.bind, c.port),
+    }
+}
+
+fn atom(
+    title: &str,
+    subtitle: &str,
+    link: &str,
+    entries: Vec<Entry>,
+) -> Result<impl IntoResponse, AppError> {
+    let updated = entries
+        .iter()
+        .map(|e| e.updated.as_str())
+        .max()
+        .unwrap_or("1970-01-01T00:00:00Z");
+
+    let mut out = String::new();
+    writeln!(out, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(out, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#)?;
+    writeln!(out, "<title>{}</title>", escape(title))?;
+    if !subtitle.is_empty() {
+        writeln!(out, "<subtitle>{}</subtitle>", escape(subtitle))?;
+    }
+    writeln!(out, r#"<link href="{}"/>"#, escape(link))?;
+    writeln!(out, "<id>{}</id>", escape(link))?;
+    writeln!(out, "<updated>{}</updated>", updated)?;
+    // atom wants an author on the feed or on every entry
+    if entries.iter().any(|e| e.author.is_empty()) {
+        writeln!(out, "<author><name>{}</name></author>", escape(title))?;
+    }
+    for e in &entries {
+        writeln!(out, "<entry>")?;
+        writeln!(out, "<title>{}</title>", escape(&e.title))?;
+        writeln!(out, r#"<link href="{}"/>"#, escape(&e.link))?;
+        // the same link on a new date is a different entry
+        writeln!(out, "<id>{}#{}</id>", escape(&e.link), e.updated)?;
+        writeln!(out, "<updated>{}</updated>", e.updated)?;
+        if !e.author.is_empty() {
+            writeln!(out, "<author>")?;
+            writeln!(out, "<name>{}</name>", escape(&e.author))?;
+            if !e.mail.is_empty() {
+                writeln!(out, "<email>{}</email>", escape(&e.mail))?;
+            }
+            writeln!(out, "</author>")?;
+        }
+        writeln!(
+            out,
+            r#"<content type="text">{}</content>"#,
+            escape(&e.content)
+        )?;
+        writeln!(out, "</entry>")?;
+    }
+    writeln!(out, "</feed>")?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        out,
+    ))
+}
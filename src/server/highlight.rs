@@ -0,0 +1,165 @@
+use super::escape;
+use git2::{Blob, Oid, Tree};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Theme, ThemeSet},
+    html::{styled_line_to_highlighted_html, IncludeBackground},
+    parsing::{SyntaxReference, SyntaxSet},
+};
+
+/// Larger files are shown as plain text
+const HIGHLIGHT_LIMIT: usize = 512 << 10;
+/// Cache is dropped as a whole once it holds this many bytes of html
+const CACHE_LIMIT: usize = 64 << 20;
+
+static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+static THEME: OnceLock<Theme> = OnceLock::new();
+static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+
+#[derive(Default)]
+struct Cache {
+    size: usize,
+    /// the same blob is highlighted differently under another name
+    lines: HashMap<(Oid, &'static str), Arc<Vec<String>>>,
+}
+
+fn syntaxes() -> &'static SyntaxSet {
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_nonewlines)
+}
+
+fn theme() -> &'static Theme {
+    THEME.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults().themes;
+        themes.remove("InspiredGitHub").unwrap_or_default()
+    })
+}
+
+/// Pick syntax from `linguist-language` in `.gitattributes` of the root
+/// tree, then file name or extension, then shebang.
+pub fn detect(
+    tree: &Tree,
+    git: &git2::Repository,
+    path: &str,
+    first_line: &str,
+) -> Option<&'static SyntaxReference> {
+    let ss = syntaxes();
+    if let Some(lang) = linguist_language(tree, git, path) {
+        if let Some(syntax) = ss.find_syntax_by_token(&lang) {
+            return Some(syntax);
+        }
+    }
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let extension = name.rsplit_once('.').map_or(name, |(_, e)| e);
+    ss.find_syntax_by_extension(name)
+        .or_else(|| ss.find_syntax_by_extension(extension))
+        .or_else(|| ss.find_syntax_by_first_line(first_line))
+        .filter(|s| s.name != "Plain Text")
+}
+
+/// Lines of the blob as html, cached by blob id and syntax
+pub fn blob(tree: &Tree, git: &git2::Repository, path: &str, blob: &Blob) -> Arc<Vec<String>> {
+    let syntax = if blob.size() > HIGHLIGHT_LIMIT {
+        None
+    } else {
+        let content = blob.content();
+        let end = content.iter().position(|&c| c == b'\n');
+        let first_line = String::from_utf8_lossy(&content[..end.unwrap_or(content.len())]);
+        detect(tree, git, path, first_line.trim_end_matches('\r'))
+    };
+    let key = (blob.id(), syntax.map_or("", |s| s.name.as_str()));
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some(lines) = cache.lock().unwrap().lines.get(&key) {
+        return lines.clone();
+    }
+
+    let text = String::from_utf8_lossy(blob.content());
+    let mut highlighter = Highlighter::new(syntax);
+    let lines: Vec<String> = text.lines().map(|l| highlighter.line(l)).collect();
+    let lines = Arc::new(lines);
+
+    let mut cache = cache.lock().unwrap();
+    let size: usize = lines.iter().map(String::len).sum();
+    if cache.size + size > CACHE_LIMIT {
+        *cache = Cache::default();
+    }
+    cache.size += size;
+    cache.lines.insert(key, lines.clone());
+    lines
+}
+
+/// Highlight line by line, parse state is carried to the next line
+pub struct Highlighter {
+    inner: Option<HighlightLines<'static>>,
+}
+
+impl Highlighter {
+    pub fn new(syntax: Option<&'static SyntaxReference>) -> Self {
+        Highlighter {
+            inner: syntax.map(|s| HighlightLines::new(s, theme())),
+        }
+    }
+
+    /// Escaped html of one line without line ending
+    pub fn line(&mut self, line: &str) -> String {
+        let Some(inner) = self.inner.as_mut() else {
+            return escape(line);
+        };
+        inner
+            .highlight_line(line, syntaxes())
+            .and_then(|regions| styled_line_to_highlighted_html(&regions, IncludeBackground::No))
+            .unwrap_or_else(|_| escape(line))
+    }
+}
+
+/// Value of the last `linguist-language=` matching the path
+fn linguist_language(tree: &Tree, git: &git2::Repository, path: &str) -> Option<String> {
+    let entry = tree.get_path(std::path::Path::new(".gitattributes")).ok()?;
+    let blob = entry.to_object(git).ok()?.peel_to_blob().ok()?;
+    let text = String::from_utf8_lossy(blob.content());
+    let mut lang = None;
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(pattern) = fields.next().filter(|p| !p.starts_with('#')) else {
+            continue;
+        };
+        let value = fields.find_map(|f| f.strip_prefix("linguist-language="));
+        if let Some(value) = value {
+            if attr_match(pattern, path) {
+                lang = Some(value.to_owned());
+            }
+        }
+    }
+    lang
+}
+
+/// Pattern without slash matches the file name, otherwise the full path
+fn attr_match(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_start_matches('/');
+    let name = if pattern.contains('/') {
+        path
+    } else {
+        path.rsplit('/').next().unwrap_or(path)
+    };
+    glob(pattern.as_bytes(), name.as_bytes())
+}
+
+/// `*` and `?` stop at `/`, `**` crosses directories
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|i| glob(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => {
+            let end = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=end).any(|i| glob(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => matches!(text, [c, t @ ..] if *c != b'/' && glob(rest, t)),
+        [p, rest @ ..] => matches!(text, [c, t @ ..] if c == p && glob(rest, t)),
+    }
+}
@@ -0,0 +1,68 @@
+use super::{AppError, Shared};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// Queue the incremental update of one repository, for mirror jobs right
+/// after a fetch. Needs `Authorization: Bearer <token>`.
+pub async fn update(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let (Some(token), Some(scheduler)) = (&c.token, &c.scheduler) else {
+        return Err(AppError::NotFound("update hook is disabled".to_string()));
+    };
+    if !authorized(&headers, token) {
+        return Ok((StatusCode::UNAUTHORIZED, "invalid token").into_response());
+    }
+    // unknown names are kept, the scheduler rescans before updating
+    scheduler.request(Some(repo));
+    Ok(StatusCode::ACCEPTED.into_response())
+}
+
+/// `Authorization: Bearer <token>` with exactly this token
+fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    given.is_some_and(|g| same(g.as_bytes(), token.as_bytes()))
+}
+
+/// Compare without returning early, the time does not leak the token
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_bytes() {
+        assert!(same(b"secret", b"secret"));
+        assert!(same(b"", b""));
+        assert!(!same(b"secret", b"secreT"));
+        assert!(!same(b"secret", b"secret2"));
+        assert!(!same(b"secret", b""));
+    }
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn bearer_token() {
+        assert!(authorized(&headers("Bearer secret"), "secret"));
+        assert!(!authorized(&headers("Bearer wrong"), "secret"));
+        assert!(!authorized(&headers("Bearer secret "), "secret"));
+        assert!(!authorized(&headers("bearer secret"), "secret"));
+        assert!(!authorized(&headers("secret"), "secret"));
+        assert!(!authorized(&HeaderMap::new(), "secret"));
+    }
+}
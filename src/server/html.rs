@@ -0,0 +1,602 @@
+use super::{
+    escape, find_repo,
+    highlight::{self, Highlighter},
+    readme::{self, Readme},
+    repo_id, repo_row, with_db, with_repo, AppError, Shared,
+};
+use crate::database::{
+    self,
+    branch::Branch,
+    change::Change,
+    commit::Log,
+    repository::{Overview, Sort},
+    tag::Tag,
+};
+use askama::Template;
+use axum::{
+    debug_handler,
+    extract::{Path, Query, State},
+    http::header,
+    response::{Html, IntoResponse},
+};
+use git2::{DiffFormat, ObjectType, Tree};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+mod filters {
+    /// Repository name or path for a link
+    pub fn segments(path: &str) -> askama::Result<String> {
+        Ok(crate::server::encode_path(path))
+    }
+
+    /// First line of the message
+    pub fn summary(msg: &str) -> askama::Result<String> {
+        Ok(msg.lines().next().unwrap_or_default().to_owned())
+    }
+
+    pub fn short(hash: &str) -> askama::Result<String> {
+        Ok(hash.chars().take(7).collect())
+    }
+
+    /// Date part of RFC 3339 time
+    pub fn day(date: &str) -> askama::Result<String> {
+        Ok(date.chars().take(10).collect())
+    }
+}
+
+fn render<T: Template>(page: T) -> Result<Html<String>, AppError> {
+    Ok(Html(page.render()?))
+}
+
+#[derive(Deserialize)]
+pub struct RevQuery {
+    /// branch, tag or commit, head of the repository by default
+    id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LogQuery {
+    #[serde(rename = "ref")]
+    refname: Option<String>,
+    /// only commits changing this file or directory
+    path: Option<String>,
+    #[serde(default)]
+    offset: u32,
+}
+
+#[derive(Deserialize)]
+pub struct IndexQuery {
+    /// substring of name or description
+    pub q: Option<String>,
+    #[serde(default)]
+    pub sort: Sort,
+}
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexPage {
+    q: String,
+    sort: Sort,
+    /// repositories without category first
+    groups: Vec<(Option<String>, Vec<Overview>)>,
+}
+
+#[debug_handler]
+pub async fn index(
+    State(c): State<Shared>,
+    Query(query): Query<IndexQuery>,
+) -> Result<Html<String>, AppError> {
+    let q = query.q.unwrap_or_default();
+    let filter = q.trim().to_owned();
+    let sort = query.sort;
+    let repos = with_db(&c, move |conn| {
+        let filter = Some(filter.as_str()).filter(|f| !f.is_empty());
+        Ok(database::repository::overview(conn, None, filter, sort)?)
+    })
+    .await?;
+    let mut groups: Vec<(Option<String>, Vec<Overview>)> = Vec::new();
+    for r in repos {
+        let group = groups
+            .iter_mut()
+            .find(|(category, _)| *category == r.category);
+        match group {
+            Some((_, group)) => group.push(r),
+            None => groups.push((r.category.clone(), vec![r])),
+        }
+    }
+    groups.sort_by(|a, b| a.0.cmp(&b.0));
+    render(IndexPage { q, sort, groups })
+}
+
+#[derive(Template)]
+#[template(path = "summary.html")]
+struct SummaryPage {
+    repo: String,
+    tab: &'static str,
+    show: String,
+    head: String,
+    logs: Vec<Log>,
+    branches: Vec<Branch>,
+    tags: Vec<Tag>,
+    readme: Option<Readme>,
+}
+
+#[debug_handler]
+pub async fn summary(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+) -> Result<Html<String>, AppError> {
+    let name = repo.clone();
+    let limit = c.limits.summary;
+    let page = with_repo(&c, &repo, move |conn, git| {
+        let r = repo_row(conn, &name)?;
+        // empty repositories have no head commit
+        let logs = match rev_commit(git, &r.head) {
+            Ok(head) => database::commit::log(conn, r.id, &head, None, limit, 0)?,
+            Err(_) => Vec::new(),
+        };
+        let branches = database::branch::list(conn, r.id)?;
+        let tags = database::tag::latest(conn, Some(r.id), limit, 0)?;
+        let readme = readme::render(git, &name, &r.head)?;
+        Ok(SummaryPage {
+            repo: name,
+            tab: "summary",
+            show: r.show,
+            head: r.head,
+            logs,
+            branches,
+            tags,
+            readme,
+        })
+    })
+    .await?;
+    render(page)
+}
+
+#[derive(Template)]
+#[template(path = "log.html")]
+struct LogPage {
+    repo: String,
+    tab: &'static str,
+    refname: String,
+    path: Option<String>,
+    logs: Vec<Log>,
+    prev: Option<u32>,
+    next: Option<u32>,
+}
+
+#[debug_handler]
+pub async fn log(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    Query(query): Query<LogQuery>,
+) -> Result<Html<String>, AppError> {
+    find_repo(&c, &repo)?;
+    let name = repo.clone();
+    let offset = query.offset;
+    let limit = c.limits.log;
+    let path = query.path.filter(|p| !p.trim_matches('/').is_empty());
+    let filter = path.clone();
+    let (refname, logs) = with_repo(&c, &repo, move |conn, git| {
+        let r = repo_row(conn, &name)?;
+        let refname = query.refname.unwrap_or(r.head);
+        let start = rev_commit(git, &refname)?;
+        let path = filter.as_deref();
+        let logs = database::commit::log(conn, r.id, &start, path, limit, offset)?;
+        Ok((refname, logs))
+    })
+    .await?;
+    let prev = (offset > 0).then(|| offset.saturating_sub(limit));
+    let next = (logs.len() == limit as usize).then_some(offset + limit);
+    render(LogPage {
+        repo,
+        tab: "log",
+        refname,
+        path,
+        logs,
+        prev,
+        next,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "refs.html")]
+struct RefsPage {
+    repo: String,
+    tab: &'static str,
+    branches: Vec<Branch>,
+    tags: Vec<Tag>,
+}
+
+#[debug_handler]
+pub async fn refs(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+) -> Result<Html<String>, AppError> {
+    find_repo(&c, &repo)?;
+    let name = repo.clone();
+    let (branches, tags) = with_db(&c, move |conn| {
+        let repo_id = repo_id(conn, &name)?;
+        let branches = database::branch::list(conn, repo_id)?;
+        let tags = database::tag::latest(conn, Some(repo_id), u32::MAX, 0)?;
+        Ok((branches, tags))
+    })
+    .await?;
+    render(RefsPage {
+        repo,
+        tab: "refs",
+        branches,
+        tags,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "tags.html")]
+struct TagsPage {
+    repo: String,
+    tab: &'static str,
+    tags: Vec<Tag>,
+}
+
+#[debug_handler]
+pub async fn tags(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+) -> Result<Html<String>, AppError> {
+    find_repo(&c, &repo)?;
+    let name = repo.clone();
+    let tags = with_db(&c, move |conn| {
+        let repo_id = repo_id(conn, &name)?;
+        Ok(database::tag::latest(conn, Some(repo_id), u32::MAX, 0)?)
+    })
+    .await?;
+    render(TagsPage {
+        repo,
+        tab: "tags",
+        tags,
+    })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Tree,
+    Blob,
+    Submodule,
+}
+
+#[derive(Serialize)]
+pub struct TreeItem {
+    name: String,
+    path: String,
+    mode: String,
+    kind: Kind,
+    id: String,
+    /// only for blob
+    size: Option<usize>,
+}
+
+/// Entries of directory `dir` at revision `rev`, directories first
+pub fn list_tree(git: &git2::Repository, rev: &str, dir: &str) -> Result<Vec<TreeItem>, AppError> {
+    let root = rev_tree(git, rev)?;
+    let tree = if dir.is_empty() {
+        root
+    } else {
+        root.get_path(std::path::Path::new(dir))
+            .and_then(|e| e.to_object(git))
+            .and_then(|o| o.peel_to_tree())
+            .map_err(|_| AppError::NotFound(format!("tree not found: {}", dir)))?
+    };
+    let odb = git.odb()?;
+    let mut entries = Vec::new();
+    for e in tree.iter() {
+        let name = e.name().unwrap_or_default().to_owned();
+        let (kind, size) = match e.kind() {
+            Some(ObjectType::Tree) => (Kind::Tree, None),
+            Some(ObjectType::Blob) => (Kind::Blob, Some(odb.read_header(e.id())?.0)),
+            _ => (Kind::Submodule, None),
+        };
+        entries.push(TreeItem {
+            path: join_path(dir, &name),
+            mode: format!("{:06o}", e.filemode()),
+            id: e.id().to_string(),
+            name,
+            kind,
+            size,
+        });
+    }
+    // directories first, like most forges
+    entries.sort_by_key(|e| !matches!(e.kind, Kind::Tree));
+    Ok(entries)
+}
+
+#[derive(Template)]
+#[template(path = "tree.html")]
+struct TreePage {
+    repo: String,
+    tab: &'static str,
+    id: String,
+    crumbs: Vec<(String, String)>,
+    entries: Vec<TreeItem>,
+}
+
+#[debug_handler]
+pub async fn tree_root(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    Query(query): Query<RevQuery>,
+) -> Result<Html<String>, AppError> {
+    tree(repo, String::new(), c, query).await
+}
+
+#[debug_handler]
+pub async fn tree_path(
+    Path((repo, path)): Path<(String, String)>,
+    State(c): State<Shared>,
+    Query(query): Query<RevQuery>,
+) -> Result<Html<String>, AppError> {
+    tree(repo, path, c, query).await
+}
+
+async fn tree(
+    repo: String,
+    path: String,
+    c: Shared,
+    query: RevQuery,
+) -> Result<Html<String>, AppError> {
+    let (dir, name) = (path.trim_matches('/').to_owned(), repo.clone());
+    let (id, entries) = with_repo(&c, &repo, move |conn, git| {
+        let id = rev_or_head(conn, &name, query.id)?;
+        let entries = list_tree(git, &id, &dir)?;
+        Ok((id, entries))
+    })
+    .await?;
+    render(TreePage {
+        tab: "tree",
+        crumbs: crumbs(&path),
+        repo,
+        id,
+        entries,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "blob.html")]
+struct BlobPage {
+    repo: String,
+    tab: &'static str,
+    id: String,
+    path: String,
+    crumbs: Vec<(String, String)>,
+    size: usize,
+    /// Highlighted html, `None` for binary file
+    lines: Option<Arc<Vec<String>>>,
+}
+
+#[debug_handler]
+pub async fn blob(
+    Path((repo, path)): Path<(String, String)>,
+    State(c): State<Shared>,
+    Query(query): Query<RevQuery>,
+) -> Result<Html<String>, AppError> {
+    let (file, name) = (path.clone(), repo.clone());
+    let (id, size, lines) = with_repo(&c, &repo, move |conn, git| {
+        let id = rev_or_head(conn, &name, query.id)?;
+        let blob = rev_blob(git, &id, &file)?;
+        let lines = if blob.is_binary() {
+            None
+        } else {
+            Some(highlight::blob(&rev_tree(git, &id)?, git, &file, &blob))
+        };
+        Ok((id, blob.size(), lines))
+    })
+    .await?;
+    render(BlobPage {
+        tab: "tree",
+        crumbs: crumbs(&path),
+        repo,
+        id,
+        path,
+        size,
+        lines,
+    })
+}
+
+#[debug_handler]
+pub async fn raw(
+    Path((repo, path)): Path<(String, String)>,
+    State(c): State<Shared>,
+    Query(query): Query<RevQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let (file, name) = (path.clone(), repo.clone());
+    let (binary, content) = with_repo(&c, &repo, move |conn, git| {
+        let rev = rev_or_head(conn, &name, query.id)?;
+        let blob = rev_blob(git, &rev, &file)?;
+        Ok((blob.is_binary(), blob.content().to_vec()))
+    })
+    .await?;
+    let extension = path.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase());
+    let mime = match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        _ if binary => "application/octet-stream",
+        _ => "text/plain; charset=utf-8",
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, mime),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            // svg may carry script, never run it on our origin
+            (
+                header::CONTENT_SECURITY_POLICY,
+                "default-src 'none'; sandbox",
+            ),
+        ],
+        content,
+    ))
+}
+
+#[derive(Template)]
+#[template(path = "commit.html")]
+struct CommitPage {
+    repo: String,
+    tab: &'static str,
+    hash: String,
+    tree: String,
+    parents: Vec<String>,
+    author: String,
+    author_date: String,
+    committer: String,
+    commit_date: String,
+    msg: String,
+    changes: Vec<Change>,
+    /// Class, origin and highlighted html of each line
+    patch: Vec<(&'static str, &'static str, String)>,
+    truncated: bool,
+}
+
+#[debug_handler]
+pub async fn commit(
+    Path((repo, hash)): Path<(String, String)>,
+    State(c): State<Shared>,
+) -> Result<Html<String>, AppError> {
+    let name = repo.clone();
+    // larger patches are cut
+    let limit = c.limits.patch;
+    let page = with_repo(&c, &repo, move |conn, git| {
+        let hash = rev_commit(git, &hash)?;
+        let commit = git.find_commit(git2::Oid::from_str(&hash)?)?;
+        let repo_id = repo_id(conn, &name)?;
+        let changes = database::change::list(conn, &hash, repo_id)?;
+
+        // patch against the first parent, like `git show --first-parent`
+        let old_tree = match commit.parent(0) {
+            Ok(p) => Some(p.tree()?),
+            Err(_) => None,
+        };
+        let new_tree = commit.tree()?;
+        let diff = git.diff_tree_to_tree(old_tree.as_ref(), Some(&new_tree), None)?;
+        let mut patch = Vec::new();
+        let mut size = 0;
+        let mut truncated = false;
+        let mut file = None;
+        let mut highlighter = Highlighter::new(None);
+        diff.print(DiffFormat::Patch, |delta, _, line| {
+            let content = String::from_utf8_lossy(line.content());
+            size += content.len();
+            if size > limit {
+                truncated = true;
+                return false;
+            }
+            // one highlighter per file, old and new lines share the state
+            let path = delta.new_file().path().or(delta.old_file().path());
+            if file.as_deref() != path {
+                file = path.map(ToOwned::to_owned);
+                let name = file.as_ref().and_then(|p| p.to_str()).unwrap_or_default();
+                let first = content.lines().next().unwrap_or_default();
+                highlighter = Highlighter::new(highlight::detect(&new_tree, git, name, first));
+            }
+            let item = match line.origin() {
+                '+' | '-' | ' ' => {
+                    let (class, origin) = match line.origin() {
+                        '+' => ("add", "+"),
+                        '-' => ("del", "-"),
+                        _ => ("", " "),
+                    };
+                    let text = content.strip_suffix('\n').unwrap_or(&content);
+                    (class, origin, highlighter.line(text) + "\n")
+                }
+                'H' => ("hunk", "", escape(&content)),
+                _ => ("meta", "", escape(&content)),
+            };
+            patch.push(item);
+            true
+        })
+        .or_else(|e| if truncated { Ok(()) } else { Err(e) })?;
+
+        let author = commit.author();
+        let committer = commit.committer();
+        Ok(CommitPage {
+            repo: name,
+            tab: "log",
+            tree: commit.tree_id().to_string(),
+            parents: commit.parent_ids().map(|p| p.to_string()).collect(),
+            author: format!(
+                "{} <{}>",
+                author.name().unwrap_or_default(),
+                author.email().unwrap_or_default()
+            ),
+            author_date: database::datetime::to_iso(author.when())?,
+            committer: format!(
+                "{} <{}>",
+                committer.name().unwrap_or_default(),
+                committer.email().unwrap_or_default()
+            ),
+            commit_date: database::datetime::to_iso(committer.when())?,
+            msg: commit.message().unwrap_or_default().to_owned(),
+            hash,
+            changes,
+            patch,
+            truncated,
+        })
+    })
+    .await?;
+    render(page)
+}
+
+/// `id` of the query, else the head branch of the repository
+pub fn rev_or_head(conn: &Connection, repo: &str, id: Option<String>) -> Result<String, AppError> {
+    match id {
+        Some(id) => Ok(id),
+        None => Ok(repo_row(conn, repo)?.head),
+    }
+}
+
+/// Hash of the commit a branch, tag or abbreviated hash points to
+pub fn rev_commit(git: &git2::Repository, rev: &str) -> Result<String, AppError> {
+    git.revparse_single(rev)
+        .and_then(|o| o.peel_to_commit())
+        .map(|c| c.id().to_string())
+        .map_err(|_| AppError::NotFound(format!("commit not found: {}", rev)))
+}
+
+pub fn rev_tree<'a>(git: &'a git2::Repository, rev: &str) -> Result<Tree<'a>, AppError> {
+    git.revparse_single(rev)
+        .and_then(|o| o.peel_to_tree())
+        .map_err(|_| AppError::NotFound(format!("revision not found: {}", rev)))
+}
+
+pub fn rev_blob<'a>(
+    git: &'a git2::Repository,
+    rev: &str,
+    path: &str,
+) -> Result<git2::Blob<'a>, AppError> {
+    rev_tree(git, rev)?
+        .get_path(std::path::Path::new(path))
+        .and_then(|e| e.to_object(git))
+        .and_then(|o| o.peel_to_blob())
+        .map_err(|_| AppError::NotFound(format!("file not found: {}", path)))
+}
+
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// (name, path) of every component
+fn crumbs(path: &str) -> Vec<(String, String)> {
+    let mut crumbs = Vec::new();
+    let mut current = String::new();
+    for name in path.split('/').filter(|n| !n.is_empty()) {
+        current = join_path(&current, name);
+        crumbs.push((name.to_owned(), current.clone()));
+    }
+    crumbs
+}
@@ -1,64 +1,356 @@
-use std::{collections::HashMap, sync::Arc};
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock, RwLock};
 
-use crate::config::Settings;
-use anyhow::Result;
+use crate::config::{Config, Repo};
+use crate::database;
+use crate::update::schedule::{Repos, Scheduler};
+use anyhow::{anyhow, Result};
 use axum::{
-    debug_handler,
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::get,
-    Router,
+    extract::{DefaultBodyLimit, Request, State},
+    http::{StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router, ServiceExt,
 };
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC};
+use rusqlite::Connection;
+use tokio::signal::unix::{signal, SignalKind};
+use tower::Layer;
+
+mod activity;
+mod api;
+mod contributor;
+mod dumb;
+mod feed;
+mod highlight;
+mod hook;
+mod html;
+mod readme;
+mod search;
+mod smart;
+
+/// Config of the server, the repository list is reloaded by the scheduler
+pub struct App {
+    config: Config,
+    repos: Repos,
+    scheduler: Option<Scheduler>,
+}
+
+impl Deref for App {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        &self.config
+    }
+}
+
+type Shared = Arc<App>;
+
+static TITLE: OnceLock<String> = OnceLock::new();
+
+/// Site title for templates, which only see their own fields
+pub fn title() -> &'static str {
+    TITLE.get().map_or("rogit", String::as_str)
+}
+
+/// Escaped in a path segment, `/` included
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Escaped in a query value
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Percent-encode each segment of a slash separated path
+pub fn encode_path(path: &str) -> String {
+    let segments: Vec<_> = path
+        .split('/')
+        .map(|s| utf8_percent_encode(s, SEGMENT).to_string())
+        .collect();
+    segments.join("/")
+}
+
+/// Percent-encode a query value
+pub fn encode_query(value: &str) -> String {
+    utf8_percent_encode(value, COMPONENT).to_string()
+}
+
+/// Escape text for html and xml, dropping control characters xml forbids
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
 
 #[tokio::main]
-pub async fn run(settings: &Settings) -> Result<()> {
-    let pairpool_map = Arc::new(HashMap::from(settings));
+pub async fn run(c: &Config) -> Result<()> {
+    let _ = TITLE.set(c.title.clone());
+    let repos: Repos = Arc::new(RwLock::new(c.repo.clone()));
+    let scheduled = c.interval.is_some() || c.repo.iter().any(|r| r.interval.is_some());
+    let scheduler = if scheduled || c.watch || c.token.is_some() {
+        // the scheduler owns rogit.db, create or migrate it first
+        c.open_db_rw()?;
+        Some(Scheduler::spawn(c, repos.clone()))
+    } else {
+        None
+    };
+    database::schema::check(&c.open_db()?)?;
+    if let Some(scheduler) = scheduler.clone() {
+        tokio::spawn(async move {
+            let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+                return;
+            };
+            while hangup.recv().await.is_some() {
+                scheduler.request(None);
+            }
+        });
+    }
+    let shared = Arc::new(App {
+        config: c.clone(),
+        repos,
+        scheduler,
+    });
     let action = Router::new()
-        .route("/refs", get(list_refs))
-        .route("/tags", get(list_refs))
-        .route("/commit/:hash", get(list_refs))
-        .route("/patch/:hash", get(list_refs))
-        .route("/tags", get(list_refs));
+        .route("/log", get(html::log))
+        .route("/tree", get(html::tree_root))
+        .route("/tree/*path", get(html::tree_path))
+        .route("/blob/*path", get(html::blob))
+        .route("/raw/*path", get(html::raw))
+        .route("/commit/:hash", get(html::commit))
+        .route("/refs", get(html::refs))
+        .route("/tags", get(html::tags))
+        .route("/search", get(search::repo))
+        .route("/contributors", get(contributor::list))
+        .route("/activity", get(activity::repo_series))
+        .route("/punchcard", get(activity::repo_punchcard))
+        .route("/feed/commits", get(feed::commits))
+        .route("/feed/tags", get(feed::tags))
+        .route("/feed/branches", get(feed::branches))
+        .route("/update", post(hook::update));
+    let api = Router::new()
+        .route("/repositories", get(api::repositories))
+        .route("/repositories/:repo", get(api::repository))
+        .route("/repositories/:repo/refs", get(api::refs))
+        .route("/repositories/:repo/branches", get(api::branches))
+        .route("/repositories/:repo/tags", get(api::tags))
+        .route("/repositories/:repo/log", get(api::log))
+        .route("/repositories/:repo/tree", get(api::tree))
+        .route("/repositories/:repo/commits/:hash", get(api::commit))
+        .route(
+            "/repositories/:repo/commits/:hash/changes",
+            get(api::changes),
+        )
+        .route("/repositories/:repo/contributors", get(api::contributors))
+        .route("/contributors/:id", get(api::contributor))
+        .fallback(api::fallback);
     let app = Router::new()
-        .route("/:repo", get(repo_path))
+        .nest("/api/v1", api)
+        .route("/-/search", get(search::all))
+        .route("/-/feed", get(feed::all))
+        .route("/-/contributors/:id", get(contributor::show))
+        .route("/-/contributors/:id/activity", get(activity::author_series))
+        .route(
+            "/-/contributors/:id/punchcard",
+            get(activity::author_punchcard),
+        )
+        .route("/", get(html::index))
+        .route("/:repo", get(html::summary))
+        .route("/:repo/info/refs", get(smart::info_refs))
+        .route("/:repo/HEAD", get(dumb::head))
+        .route("/:repo/objects/info/packs", get(dumb::packs))
+        .route("/:repo/objects/pack/:file", get(dumb::pack))
+        .route("/:repo/objects/:dir/:file", get(dumb::loose))
+        .route(
+            "/:repo/git-upload-pack",
+            post(smart::upload).layer(DefaultBodyLimit::max(c.limits.upload)),
+        )
         .nest("/:repo/-", action)
-        .with_state(pairpool_map.clone());
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await?;
+        .with_state(shared.clone());
+    // before routing, `:repo` only takes one segment
+    let app = middleware::from_fn_with_state(shared, nested).layer(app);
+    let listener = tokio::net::TcpListener::bind((c.bind, c.port)).await?;
+    axum::serve(listener, ServiceExt::<Request>::into_make_service(app)).await?;
     Ok(())
 }
 
-#[debug_handler]
-async fn repo_path(
-    Path(repo): Path<String>,
-    State(pool): State<Arc<HashMap<String, PairPool>>>,
-) -> impl IntoResponse {
-    let pool_git = &pool.get(&repo).unwrap().git.get().await.unwrap();
-    let path: String = pool_git
-        .interact(|repo| repo.path().to_string_lossy().into())
-        .await
-        .unwrap();
-    (StatusCode::OK, path)
-}
-
-#[debug_handler]
-async fn list_refs(
-    Path(repo): Path<String>,
-    State(pool): State<Arc<HashMap<String, PairPool>>>,
-) -> Result<String, (StatusCode, String)> {
-    let pool_db = &pool.get(&repo).unwrap().sqlite.get().await.unwrap();
-    let refs = pool_db
-        .interact(|conn| {
-            let mut stmt = conn.prepare("SELECT name FROM branch").unwrap();
-            let rows = stmt.query_map([], |row| row.get::<_, String>(0)).unwrap();
-            let mut tags = Vec::new();
-            for row in rows {
-                tags.push(row.unwrap());
+/// Prefixes followed by a repository name in urls
+const REPO_PREFIXES: [&str; 2] = ["/api/v1/repositories/", "/"];
+
+/// Encode the slashes of nested names, `/group/project/-/log` is routed
+/// as `/group%2Fproject/-/log`. Repositories are never found inside one
+/// another and no name has a `-` segment, so at most one name matches.
+async fn nested(State(c): State<Shared>, mut req: Request, next: Next) -> Response {
+    let uri = encode_repo(&c.repos.read().unwrap(), req.uri());
+    if let Some(uri) = uri {
+        *req.uri_mut() = uri;
+    }
+    next.run(req).await
+}
+
+/// Links encode each segment of the name, so compare encoded names
+fn encode_repo(repos: &[Repo], uri: &Uri) -> Option<Uri> {
+    let path = uri.path();
+    let (prefix, rest) = REPO_PREFIXES
+        .iter()
+        .find_map(|p| Some((*p, path.strip_prefix(p)?)))?;
+    let name = repos
+        .iter()
+        .filter(|r| r.name.contains('/'))
+        .map(|r| encode_path(&r.name))
+        .find(|name| {
+            rest.strip_prefix(name.as_str())
+                .is_some_and(|tail| tail.is_empty() || tail.starts_with('/'))
+        })?;
+    let tail = &rest[name.len()..];
+    let mut encoded = format!("{}{}{}", prefix, name.replace('/', "%2F"), tail);
+    if let Some(query) = uri.query() {
+        encoded = format!("{}?{}", encoded, query);
+    }
+    encoded.parse().ok()
+}
+
+pub enum AppError {
+    NotFound(String),
+    BadRequest(String),
+    Internal(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for AppError {
+    fn from(e: E) -> Self {
+        AppError::Internal(e.into())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        match self {
+            AppError::NotFound(m) => (StatusCode::NOT_FOUND, m).into_response(),
+            AppError::BadRequest(m) => (StatusCode::BAD_REQUEST, m).into_response(),
+            AppError::Internal(e) => {
+                // paths and sql stay in the log
+                eprintln!("[server] error: {:#}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal error").into_response()
             }
-            tags
-        })
+        }
+    }
+}
+
+/// Run `f` on a fresh read only connection outside of the async runtime
+async fn with_db<T, F>(c: &Shared, f: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Connection) -> Result<T, AppError> + Send + 'static,
+{
+    let c = c.clone();
+    tokio::task::spawn_blocking(move || f(&c.open_db()?))
         .await
-        .unwrap();
-    Ok(refs.join("\n"))
+        .map_err(|e| anyhow!(e))?
+}
+
+/// Like `with_db`, also opens the git repository
+async fn with_repo<T, F>(c: &Shared, name: &str, f: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Connection, &git2::Repository) -> Result<T, AppError> + Send + 'static,
+{
+    let path = find_repo(c, name)?.path;
+    with_db(c, move |conn| f(conn, &git2::Repository::open_bare(path)?)).await
+}
+
+/// Id of the repository, not found before its first update
+fn repo_id(conn: &Connection, name: &str) -> Result<i64, AppError> {
+    database::repository::get_id(conn, name)?
+        .ok_or_else(|| AppError::NotFound(format!("repository not updated yet: {}", name)))
+}
+
+/// Row of the repository, not found before its first update
+fn repo_row(conn: &Connection, name: &str) -> Result<database::repository::Repository, AppError> {
+    database::repository::get(conn, name)?
+        .ok_or_else(|| AppError::NotFound(format!("repository not updated yet: {}", name)))
+}
+
+fn find_repo(c: &App, name: &str) -> Result<Repo, AppError> {
+    c.repos
+        .read()
+        .unwrap()
+        .iter()
+        .find(|r| r.name == name)
+        .cloned()
+        .ok_or_else(|| AppError::NotFound(format!("repository not found: {}", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repos(names: &[&str]) -> Vec<Repo> {
+        names
+            .iter()
+            .map(|name| Repo {
+                name: name.to_string(),
+                path: name.into(),
+                interval: None,
+                upstream: None,
+                meta: Default::default(),
+            })
+            .collect()
+    }
+
+    fn encode(repos: &[Repo], uri: &str) -> Option<String> {
+        encode_repo(repos, &uri.parse().unwrap()).map(|u| u.to_string())
+    }
+
+    #[test]
+    fn encode_nested_names() {
+        let repos = repos(&["top", "group/project", "group/deep/x"]);
+        let uri = encode(&repos, "/group/project/-/log?ref=main");
+        assert_eq!(uri.as_deref(), Some("/group%2Fproject/-/log?ref=main"));
+        let uri = encode(&repos, "/group/deep/x");
+        assert_eq!(uri.as_deref(), Some("/group%2Fdeep%2Fx"));
+        let uri = encode(&repos, "/api/v1/repositories/group/project/log");
+        let api = "/api/v1/repositories/group%2Fproject/log";
+        assert_eq!(uri.as_deref(), Some(api));
+    }
+
+    #[test]
+    fn encode_leaves_other_paths() {
+        let repos = repos(&["top", "group/project"]);
+        assert_eq!(encode(&repos, "/top/-/log"), None);
+        assert_eq!(encode(&repos, "/group"), None);
+        // a prefix of the name only
+        assert_eq!(encode(&repos, "/group/projectx/-/log"), None);
+        assert_eq!(encode(&repos, "/-/search?q=group/project"), None);
+    }
+
+    #[test]
+    fn encode_special_segments() {
+        let repos = repos(&["my group/a b.git"]);
+        let uri = encode(&repos, "/my%20group/a%20b.git/-/tree");
+        assert_eq!(uri.as_deref(), Some("/my%20group%2Fa%20b.git/-/tree"));
+        assert_eq!(encode_path("my group/a#b?.git"), "my%20group/a%23b%3F.git");
+    }
 }
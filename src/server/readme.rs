@@ -0,0 +1,113 @@
+use super::{encode_path, encode_query, AppError};
+use git2::{ObjectType, Repository, Tree};
+use percent_encoding::percent_decode_str;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+/// Larger README is not rendered
+const README_LIMIT: usize = 512 << 10;
+
+/// Checked in order, the first one found wins
+const README_NAMES: [&str; 6] = [
+    "readme.md",
+    "readme.markdown",
+    "readme",
+    "readme.txt",
+    "readme.rst",
+    "readme.adoc",
+];
+
+pub enum Readme {
+    /// sanitized html from markdown
+    Html(String),
+    Text(String),
+}
+
+/// Find and render README in the root of `rev`
+pub fn render(git: &Repository, repo: &str, rev: &str) -> Result<Option<Readme>, AppError> {
+    let Ok(tree) = git.revparse_single(rev).and_then(|o| o.peel_to_tree()) else {
+        return Ok(None);
+    };
+    let found = README_NAMES.iter().find_map(|want| {
+        tree.iter().find(|e| {
+            e.kind() == Some(ObjectType::Blob)
+                && e.name().is_some_and(|n| n.eq_ignore_ascii_case(want))
+        })
+    });
+    let Some(entry) = found else {
+        return Ok(None);
+    };
+    let blob = entry.to_object(git)?.peel_to_blob()?;
+    if blob.is_binary() || blob.size() > README_LIMIT {
+        return Ok(None);
+    }
+    let text = String::from_utf8_lossy(blob.content()).into_owned();
+    let name = entry.name().unwrap_or_default().to_ascii_lowercase();
+    if !(name.ends_with(".md") || name.ends_with(".markdown")) {
+        return Ok(Some(Readme::Text(text)));
+    }
+
+    let parser = Parser::new_ext(&text, Options::all()).map(|event| match event {
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            dest_url: rewrite(dest_url, &tree, repo, rev, false),
+            link_type,
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            dest_url: rewrite(dest_url, &tree, repo, rev, true),
+            link_type,
+            title,
+            id,
+        }),
+        _ => event,
+    });
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, parser);
+    Ok(Some(Readme::Html(ammonia::clean(&unsafe_html))))
+}
+
+/// Point links relative to the repository root at our tree, blob and raw
+/// routes. Absolute URLs and anchors are kept.
+fn rewrite<'a>(url: CowStr<'a>, tree: &Tree, repo: &str, rev: &str, image: bool) -> CowStr<'a> {
+    if url.is_empty() || url.starts_with('#') || url.starts_with("//") || url.contains(':') {
+        return url;
+    }
+    let (path, fragment) = match url.split_once('#') {
+        Some((path, fragment)) => (path, format!("#{}", fragment)),
+        None => (url.as_ref(), String::new()),
+    };
+    let path = path.split_once('?').map_or(path, |(p, _)| p);
+    // README is in the root, so `/` and `./` mean the same
+    let mut parts: Vec<String> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(percent_decode_str(part).decode_utf8_lossy().into_owned()),
+        }
+    }
+    let path = parts.join("/");
+    let is_tree = path.is_empty()
+        || tree
+            .get_path(std::path::Path::new(&path))
+            .is_ok_and(|e| e.kind() == Some(ObjectType::Tree));
+    let route = match (is_tree, image) {
+        (true, _) => "tree",
+        (false, true) => "raw",
+        (false, false) => "blob",
+    };
+    let (repo, path, rev) = (encode_path(repo), encode_path(&path), encode_query(rev));
+    format!("/{}/-/{}/{}?id={}{}", repo, route, path, rev, fragment).into()
+}
@@ -0,0 +1,82 @@
+use super::{find_repo, repo_id, with_db, AppError, Shared};
+use crate::database;
+use axum::{
+    debug_handler,
+    extract::{Path, Query, State},
+};
+use regex::Regex;
+use serde::Deserialize;
+use std::fmt::Write;
+
+/// Matched lines written to the response, the rest of the files are skipped
+const SEARCH_LINE_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    /// literal text, used to query the trigram index
+    q: String,
+    /// optional regex, select lines among the matched files
+    re: Option<String>,
+}
+
+#[debug_handler]
+pub async fn all(
+    State(c): State<Shared>,
+    Query(query): Query<SearchQuery>,
+) -> Result<String, AppError> {
+    search(&c, None, query).await
+}
+
+#[debug_handler]
+pub async fn repo(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    Query(query): Query<SearchQuery>,
+) -> Result<String, AppError> {
+    find_repo(&c, &repo)?;
+    search(&c, Some(repo), query).await
+}
+
+async fn search(c: &Shared, repo: Option<String>, query: SearchQuery) -> Result<String, AppError> {
+    if query.q.chars().count() < 3 {
+        return Err(AppError::BadRequest(
+            "query needs at least 3 characters".into(),
+        ));
+    }
+    let re = match &query.re {
+        Some(re) => Some(Regex::new(re).map_err(|e| AppError::BadRequest(e.to_string()))?),
+        None => None,
+    };
+    let text = query.q.clone();
+    let limit = c.limits.search;
+    let hits = with_db(c, move |conn| {
+        let repo_id = match repo {
+            Some(name) => Some(repo_id(conn, &name)?),
+            None => None,
+        };
+        Ok(database::code::search(conn, &text, repo_id, limit)?)
+    })
+    .await?;
+
+    // grep style: repo:path:line: text
+    let text = query.q.to_lowercase();
+    let mut out = String::new();
+    let mut count = 0;
+    'hits: for hit in hits {
+        for (no, line) in hit.content.lines().enumerate() {
+            let matched = match &re {
+                Some(re) => re.is_match(line),
+                None => line.to_lowercase().contains(&text),
+            };
+            if !matched {
+                continue;
+            }
+            writeln!(out, "{}:{}:{}: {}", hit.repo, hit.path, no + 1, line)?;
+            count += 1;
+            if count >= SEARCH_LINE_LIMIT {
+                break 'hits;
+            }
+        }
+    }
+    Ok(out)
+}
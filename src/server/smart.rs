@@ -0,0 +1,159 @@
+use super::{dumb, find_repo, AppError, Shared};
+use crate::config::Repo;
+use anyhow::anyhow;
+use axum::{
+    body::{Body, Bytes},
+    debug_handler,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::{io::Read, process::Stdio};
+use tokio::{io::AsyncWriteExt, process::Command};
+use tokio_util::io::ReaderStream;
+
+#[derive(Deserialize)]
+pub struct InfoRefsQuery {
+    service: Option<String>,
+}
+
+/// `git clone http://host/repo` and `http://host/repo.git` both work
+pub fn find_git_repo(c: &Shared, name: &str) -> Result<Repo, AppError> {
+    find_repo(c, name).or_else(|e| match name.strip_suffix(".git") {
+        Some(name) => find_repo(c, name),
+        None => Err(e),
+    })
+}
+
+#[debug_handler]
+pub async fn info_refs(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    Query(query): Query<InfoRefsQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let path = find_git_repo(&c, &repo)?.path.clone();
+    match query.service.as_deref() {
+        Some("git-upload-pack") => {}
+        Some(s) => {
+            return Ok(
+                (StatusCode::FORBIDDEN, format!("service not enabled: {}", s)).into_response(),
+            )
+        }
+        None => return dumb::info_refs(path).await,
+    }
+
+    let protocol = git_protocol(&headers);
+    let mut cmd = upload_pack(&path, protocol);
+    cmd.arg("--advertise-refs");
+    let output = cmd.output().await?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "upload-pack failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))?;
+    }
+
+    // v2 clients expect the capability advertisement only
+    let mut body = Vec::new();
+    if !protocol.is_some_and(|p| p.contains("version=2")) {
+        body.extend(pkt_line("# service=git-upload-pack\n"));
+        body.extend(b"0000");
+    }
+    body.extend(output.stdout);
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "application/x-git-upload-pack-advertisement",
+            ),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[debug_handler]
+pub async fn upload(
+    Path(repo): Path<String>,
+    State(c): State<Shared>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let path = find_git_repo(&c, &repo)?.path.clone();
+    let input = match headers.get(header::CONTENT_ENCODING) {
+        Some(v) if v == "gzip" => {
+            let mut input = Vec::new();
+            GzDecoder::new(&body[..])
+                .take(c.limits.upload as u64 + 1)
+                .read_to_end(&mut input)
+                .map_err(|e| AppError::BadRequest(format!("bad gzip body: {}", e)))?;
+            if input.len() > c.limits.upload {
+                return Err(AppError::BadRequest("request body too large".into()));
+            }
+            Bytes::from(input)
+        }
+        Some(v) => {
+            return Err(AppError::BadRequest(format!(
+                "unsupported content encoding: {:?}",
+                v
+            )))
+        }
+        None => body,
+    };
+
+    let mut child = upload_pack(&path, git_protocol(&headers))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("no stdin"))?;
+    let stdout = child.stdout.take().ok_or_else(|| anyhow!("no stdout"))?;
+    // feed the request while the pack is streamed back
+    tokio::spawn(async move {
+        if let Err(e) = stdin.write_all(&input).await {
+            eprintln!("[server] upload-pack stdin: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(s) if !s.success() => eprintln!("[server] upload-pack exit: {}", s),
+            Err(e) => eprintln!("[server] upload-pack wait: {}", e),
+            _ => {}
+        }
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-git-upload-pack-result"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        Body::from_stream(ReaderStream::new(stdout)),
+    )
+        .into_response())
+}
+
+fn upload_pack(path: &std::path::Path, protocol: Option<&str>) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("upload-pack")
+        .arg("--stateless-rpc")
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(p) = protocol {
+        cmd.env("GIT_PROTOCOL", p);
+    }
+    cmd.arg(path);
+    cmd
+}
+
+fn git_protocol(headers: &HeaderMap) -> Option<&str> {
+    headers.get("git-protocol").and_then(|v| v.to_str().ok())
+}
+
+fn pkt_line(s: &str) -> Vec<u8> {
+    let mut line = format!("{:04x}", s.len() + 4).into_bytes();
+    line.extend(s.as_bytes());
+    line
+}
@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Context, Result};
+use git2::{AutotagOption, FetchOptions, FetchPrune, RemoteCallbacks, Repository};
+use std::cell::Cell;
+use std::ffi::c_int;
+use std::time::{Duration, Instant};
+
+/// Refspecs of remotes without configured ones, like `git clone --bare`
+const MIRROR_REFSPECS: [&str; 2] = ["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"];
+
+/// Bound connecting to the remote and each read from it, so a silent remote
+/// fails instead of hanging. libgit2 keeps these in globals, this must run
+/// before any thread is spawned.
+pub fn set_timeout(timeout: Duration) -> Result<()> {
+    let ms = c_int::try_from(timeout.as_millis()).unwrap_or(c_int::MAX);
+    // SAFETY: called once from main, no other thread uses libgit2 yet
+    unsafe {
+        git2::opts::set_server_connect_timeout_in_milliseconds(ms)?;
+        git2::opts::set_server_timeout_in_milliseconds(ms)?;
+    }
+    Ok(())
+}
+
+/// Fetch `url` into the repository, pruning deleted refs, and return the
+/// number of updated refs. Transfer is cancelled once `timeout` is exceeded,
+/// checked whenever the remote sends data, a silent remote is left to the
+/// libgit2 timeouts of `set_timeout`.
+pub fn fetch(repo: &Repository, url: &str, timeout: Duration) -> Result<usize> {
+    let mut remote = match repo.find_remote("origin") {
+        Ok(remote) if remote.url() == Some(url) => remote,
+        _ => repo.remote_anonymous(url)?,
+    };
+    let mut refspecs: Vec<String> = remote
+        .fetch_refspecs()?
+        .iter()
+        .flatten()
+        .map(str::to_owned)
+        .collect();
+    if refspecs.is_empty() {
+        refspecs = MIRROR_REFSPECS.map(str::to_owned).to_vec();
+    }
+
+    let deadline = Instant::now() + timeout;
+    let cancelled = Cell::new(false);
+    let in_time = || {
+        let ok = Instant::now() < deadline;
+        if !ok {
+            cancelled.set(true);
+        }
+        ok
+    };
+    let mut updated = 0;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks
+        .transfer_progress(move |_| in_time())
+        .sideband_progress(move |_| in_time())
+        .update_tips(|_, _, _| {
+            updated += 1;
+            true
+        });
+    let mut options = FetchOptions::new();
+    options
+        .remote_callbacks(callbacks)
+        .prune(FetchPrune::On)
+        .download_tags(AutotagOption::All);
+
+    let result = remote.fetch(&refspecs, Some(&mut options), Some("rogit: fetch"));
+    if result.is_err() && cancelled.get() {
+        let secs = timeout.as_secs();
+        return Err(anyhow!("fetch {} timed out after {}s", url, secs));
+    }
+    result.with_context(|| format!("failed to fetch {}", url))?;
+    drop(options);
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+
+    /// Bare repository with one commit on `main` and tag `v1`
+    fn upstream(path: &std::path::Path) -> Repository {
+        let repo = Repository::init_bare(path).unwrap();
+        let sig = Signature::now("T", "t@t").unwrap();
+        let tree = repo.treebuilder(None).unwrap().write().unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        let id = repo
+            .commit(Some("refs/heads/main"), &sig, &sig, "init", &tree, &[])
+            .unwrap();
+        let commit = repo.find_object(id, None).unwrap();
+        repo.tag_lightweight("v1", &commit, false).unwrap();
+        drop(tree);
+        drop(commit);
+        repo
+    }
+
+    #[test]
+    fn fetch_from_file_remote() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = upstream(&dir.path().join("upstream.git"));
+        let repo = Repository::init_bare(dir.path().join("mirror.git")).unwrap();
+        let url = format!("file://{}", source.path().display());
+        let timeout = Duration::from_secs(30);
+
+        assert_eq!(fetch(&repo, &url, timeout).unwrap(), 2);
+        let main = repo.refname_to_id("refs/heads/main").unwrap();
+        assert_eq!(main, source.refname_to_id("refs/heads/main").unwrap());
+        assert!(repo.find_reference("refs/tags/v1").is_ok());
+        // nothing changed upstream
+        assert_eq!(fetch(&repo, &url, timeout).unwrap(), 0);
+
+        // deleted upstream, pruned here
+        source
+            .find_reference("refs/tags/v1")
+            .unwrap()
+            .delete()
+            .unwrap();
+        fetch(&repo, &url, timeout).unwrap();
+        assert!(repo.find_reference("refs/tags/v1").is_err());
+    }
+
+    #[test]
+    fn fetch_missing_remote() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init_bare(dir.path().join("mirror.git")).unwrap();
+        let url = format!("file://{}", dir.path().join("none.git").display());
+        let e = fetch(&repo, &url, Duration::from_secs(30)).unwrap_err();
+        assert!(format!("{:#}", e).contains("failed to fetch"));
+    }
+}
@@ -1,223 +1,546 @@
 use anyhow::{anyhow, Result};
 use core::str;
-use git2::{Commit, DiffOptions, Oid, Repository};
-use rusqlite::Connection;
-use rusqlite::Transaction;
-use std::fs;
+use git2::{
+    BranchType, DiffOptions, ErrorCode, ObjectType, Oid, Repository, Sort, TreeWalkMode,
+    TreeWalkResult,
+};
+use rusqlite::{Connection, Transaction};
+use std::collections::HashSet;
+use std::ops::AddAssign;
+use std::sync::Mutex;
+use std::{fs, mem, thread};
+use walkdir::WalkDir;
 
-use crate::config::Config;
-use crate::database;
+use crate::config::{Config, Repo};
+use crate::database::{self, commit::Record, contributor::Person};
+use progress::Progress;
+use writer::{Pending, Writer};
+
+pub mod fetch;
+mod progress;
+pub mod schedule;
+mod watch;
+mod writer;
+
+/// Larger blobs are not indexed for code search
+const CODE_SIZE_LIMIT: usize = 1 << 20;
+/// Commits written per transaction
+const COMMIT_BATCH: usize = 1_000;
+/// Code files written per transaction
+const CODE_BATCH: usize = 1_000;
+
+/// Rows inserted or updated by the update
+#[derive(Default)]
+struct Stats {
+    commits: u64,
+    changes: u64,
+    refs: u64,
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Self) {
+        self.commits += other.commits;
+        self.changes += other.changes;
+        self.refs += other.refs;
+    }
+}
 
 pub fn run(c: &Config) -> Result<()> {
     let mut conn = c.open_db()?;
-    for r in &c.repo {
-        let repo = Repository::open_bare(&r.path)?;
-        let repo_id = update_repository(&conn, &repo, &r.name)?;
-        update_commit_all(&mut conn, &repo, repo_id)?;
-        // branch_sync(&mut conn, &repo)?;
-        // tag_sync(&mut conn, &repo)?;
-    }
-    database::cleanup(&conn)?;
+    let repos: Vec<&Repo> = match &c.target {
+        Some(name) => vec![c
+            .repo
+            .iter()
+            .find(|r| &r.name == name)
+            .ok_or_else(|| anyhow!("repository not found: {}", name))?],
+        None => c.repo.iter().collect(),
+    };
+    update(c, &mut conn, &repos)
+}
+
+/// Repositories are read by `Config::jobs` workers, all rows go through
+/// the single writer running on this thread. A failed repository is
+/// recorded and skipped, the run fails after updating all others.
+/// Repositories missing in `Config::repo` are dropped.
+pub fn update(c: &Config, conn: &mut Connection, repos: &[&Repo]) -> Result<()> {
+    let (writer, jobs) = Writer::new();
+    let queue = Mutex::new(repos.iter());
+    let total = Mutex::new(Stats::default());
+    let errors = Mutex::new(Vec::new());
+
+    thread::scope(|s| {
+        for _ in 0..c.jobs.min(repos.len()) {
+            let writer = writer.clone();
+            let (queue, total, errors) = (&queue, &total, &errors);
+            s.spawn(move || loop {
+                let Some(r) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                match update_one(c, &writer, r) {
+                    Ok(stats) => *total.lock().unwrap() += stats,
+                    Err(e) => {
+                        eprintln!("[update] {}: {:#}", r.name, e);
+                        let (name, error) = (r.name.clone(), format!("{:#}", e));
+                        if let Err(e) =
+                            writer.call(move |conn| database::repository::fail(conn, &name, &error))
+                        {
+                            eprintln!("[update] {}: {:#}", r.name, e);
+                        }
+                        errors.lock().unwrap().push(r.name.as_str());
+                    }
+                }
+            });
+        }
+        // jobs end once the last worker drops its clone
+        drop(writer);
+        writer::serve(conn, jobs);
+    });
+
+    let total = total.into_inner().unwrap();
+    let errors = errors.into_inner().unwrap();
+    println!(
+        "[update] summary: {} commits, {} changes, {} refs, {} errors",
+        total.commits,
+        total.changes,
+        total.refs,
+        errors.len()
+    );
+    let names: Vec<&str> = c.repo.iter().map(|r| r.name.as_str()).collect();
+    let pruned = database::repository::prune(conn, &names)?;
+    if pruned > 0 {
+        println!("[update] removed: {} repositories", pruned);
+    }
+    if c.vacuum {
+        vacuum(conn)?;
+    }
+    if !database::checkpoint(conn)? {
+        println!("[database] checkpoint: readers busy, wal kept");
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!(
+            "failed to update {} of {} repositories: {}",
+            errors.len(),
+            repos.len(),
+            errors.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Drop history no ref reaches anymore, before the activity rollup
+fn collect_garbage(writer: &Writer, name: &str, repo_id: i64) -> Result<()> {
+    let r = writer.call(move |conn| {
+        let tx = conn.transaction()?;
+        let r = database::gc::collect(&tx, &[repo_id])?;
+        tx.commit()?;
+        Ok(r)
+    })?;
+    println!(
+        "[gc] {}: reclaimed {} commits, {} relations, {} changes, {} messages, {} contributors, {} editfiles",
+        name, r.commits, r.relations, r.changes, r.messages, r.contributors, r.editfiles
+    );
     Ok(())
 }
 
-fn update_repository(conn: &Connection, repo: &Repository, repo_name: &str) -> Result<i64> {
-    let head_ref = repo.head()?;
-    let head_name = head_ref.shorthand().expect("head should have valid name");
-    if !head_ref.is_branch() {
-        return Err(anyhow!("head should be branch: {}", head_name));
+fn vacuum(conn: &Connection) -> Result<()> {
+    let before = conn.query_row("PRAGMA page_count", [], |r| r.get::<_, i64>(0))?;
+    conn.execute_batch("VACUUM")?;
+    let after = conn.query_row("PRAGMA page_count", [], |r| r.get::<_, i64>(0))?;
+    println!("[gc] vacuum: {} pages freed", before - after);
+    Ok(())
+}
+
+fn update_one(c: &Config, writer: &Writer, r: &Repo) -> Result<Stats> {
+    let repo = Repository::open_bare(&r.path)?;
+    if let (true, Some(url)) = (c.fetch, &r.upstream) {
+        let updated = fetch::fetch(&repo, url, c.fetch_timeout)?;
+        println!("[fetch] {}: {} refs updated", r.name, updated);
+    }
+    let (repo_id, head) = update_repository(writer, &repo, r)?;
+    let (mut stats, rewritten) = update_commit_all(writer, &repo, repo_id, &r.name)?;
+    stats.refs += update_branch(writer, &repo, repo_id)?;
+    stats.refs += update_tag(writer, &repo, repo_id)?;
+    writer.call(move |conn| database::cleanup_refs(conn, repo_id))?;
+    if rewritten {
+        collect_garbage(writer, &r.name, repo_id)?;
     }
-    let desc = fs::read_to_string(repo.path().join("description")).unwrap_or_default();
-    database::repository::insert(conn, repo_name, &desc, head_name)?;
-    let id = database::repository::get_id(conn, repo_name)?;
-    Ok(id)
+    // gc also drops the rollup of collected repositories
+    if stats.commits > 0 || rewritten {
+        update_activity(writer, repo_id)?;
+    }
+    update_code(writer, &repo, repo_id, &head)?;
+    Ok(stats)
 }
 
-fn update_commit_all(conn: &mut Connection, repo: &Repository, repo_id: i64) -> Result<u32> {
-    let mut count = 0;
-    let ref_all = repo.references()?;
-    for ref_one in ref_all.flatten() {
-        let Ok(commit) = ref_one.peel_to_commit() else {
+/// Returns the id and the head branch, `default-branch` if it exists
+fn update_repository(writer: &Writer, repo: &Repository, r: &Repo) -> Result<(i64, String)> {
+    let head_name = match &r.meta.default_branch {
+        Some(branch) if repo.find_branch(branch, BranchType::Local).is_ok() => branch.clone(),
+        Some(branch) => {
+            eprintln!("[update] {}: default branch {} not found", r.name, branch);
+            head_name(repo)?
+        }
+        None => head_name(repo)?,
+    };
+    let name = r.name.clone();
+    let meta = r.meta.clone();
+    let desc = match &r.meta.description {
+        Some(desc) => desc.clone(),
+        None => fs::read_to_string(repo.path().join("description")).unwrap_or_default(),
+    };
+    let size = WalkDir::new(repo.path())
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum();
+    let head = head_name.clone();
+    let id = writer.call(move |conn| {
+        database::repository::insert(conn, &name, &desc, &head_name, size, &meta)?;
+        let id = database::repository::get_id(conn, &name)?
+            .ok_or_else(|| anyhow!("repository not found: {}", name))?;
+        database::mark_refs(conn, id)?;
+        Ok(id)
+    })?;
+    Ok((id, head))
+}
+
+/// Name of the head branch, also of an unborn one in empty repositories.
+/// Detached head takes a branch at the same commit, or `HEAD` if none.
+fn head_name(repo: &Repository) -> Result<String> {
+    let head = match repo.head() {
+        Ok(head) => head,
+        Err(e) if e.code() == ErrorCode::UnbornBranch => {
+            let head = repo.find_reference("HEAD")?;
+            let target = head.symbolic_target().unwrap_or("HEAD");
+            let name = target.strip_prefix("refs/heads/").unwrap_or(target);
+            return Ok(name.to_owned());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if head.is_branch() {
+        let name = head
+            .shorthand()
+            .ok_or(anyhow!("head name is not valid utf-8"))?;
+        return Ok(name.to_owned());
+    }
+    for (branch, _) in repo.branches(Some(BranchType::Local))?.flatten() {
+        if branch.get().target() != head.target() {
             continue;
-        };
-        count += update_commit_from(conn, repo, repo_id, commit.id())?;
+        }
+        if let Some(name) = branch.name()? {
+            return Ok(name.to_owned());
+        }
     }
-    Ok(count)
+    Ok("HEAD".to_owned())
 }
 
-pub fn update_commit_from(
-    conn: &mut Connection,
+/// Walk only commits not reachable from the tips of the last update,
+/// parents first, so the parent rows always exist before their children.
+/// Diffs are computed here and written by batches in the writer. Also
+/// tells whether an old tip is no longer reachable, its history is garbage.
+fn update_commit_all(
+    writer: &Writer,
     repo: &Repository,
     repo_id: i64,
-    hash: Oid,
-) -> Result<u32> {
-    let mut count = 0;
-    let mut obj_vec: Vec<(Oid, Option<i64>)> = vec![(hash, None)];
+    repo_name: &str,
+) -> Result<(Stats, bool)> {
+    let mut tips = Vec::new();
+    for r in repo.references()?.flatten() {
+        let (Some(name), Ok(commit)) = (r.name(), r.peel_to_commit()) else {
+            continue;
+        };
+        tips.push((name.to_owned(), commit.id().to_string()));
+    }
+    // history only reachable from detached head
+    if repo.head_detached()? {
+        let commit = repo.head()?.peel_to_commit()?;
+        tips.push(("HEAD".to_owned(), commit.id().to_string()));
+    }
 
-    while let Some((oid, child_id)) = obj_vec.pop() {
-        let c = repo.find_commit(oid)?;
-        let id = update_commit_one(conn, &c, repo_id, child_id, repo)?;
-        if id.is_none() {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    let mut heads = HashSet::with_capacity(tips.len());
+    for (_, hash) in &tips {
+        let oid = Oid::from_str(hash)?;
+        walk.push(oid)?;
+        heads.insert(oid);
+    }
+    // a recorded tip is only a boundary while its commit is stored
+    let recorded = writer.call(move |conn| {
+        let mut tips = Vec::new();
+        for hash in database::reftip::list(conn, repo_id)? {
+            let indexed = database::commit::get_id_by_hash(conn, &hash, repo_id)?.is_some();
+            tips.push((hash, indexed));
+        }
+        Ok(tips)
+    })?;
+    let mut rewritten = false;
+    for (hash, indexed) in recorded {
+        let Ok(oid) = Oid::from_str(&hash) else {
             continue;
+        };
+        // old tip is gone after force push, branch deletion or gc, only
+        // tips that moved need the walk
+        if !rewritten && !heads.contains(&oid) {
+            rewritten = !heads
+                .iter()
+                .any(|&head| repo.graph_descendant_of(head, oid).unwrap_or(false));
         }
-        count += 1;
-        for p in c.parent_ids() {
-            obj_vec.push((p, id));
+        if indexed && repo.find_commit(oid).is_ok() {
+            walk.hide(oid)?;
+        }
+    }
+    let oids = walk.collect::<Result<Vec<_>, _>>()?;
+
+    let mut progress = Progress::new(repo_name, oids.len());
+    // merges list files only, other commits also count lines of text
+    let mut options = DiffOptions::new();
+    options.skip_binary_check(true).force_binary(true);
+    let mut text = DiffOptions::new();
+    text.max_size(CODE_SIZE_LIMIT as i64);
+    let mut stats = Stats::default();
+    let mut pending: Option<Pending<Stats>> = None;
+    let mut batch = Vec::with_capacity(COMMIT_BATCH);
+    for oid in oids {
+        let commit = repo.find_commit(oid)?;
+        let tree = commit.tree()?;
+        let mut parents = Vec::new();
+        let lines = commit.parent_count() == 1;
+        for parent in commit.parents() {
+            let options = if lines { &mut text } else { &mut options };
+            let diff = repo.diff_tree_to_tree(Some(&parent.tree()?), Some(&tree), Some(options))?;
+            let files = database::change::files(&diff, lines)?;
+            parents.push((parent.id().to_string(), files));
+        }
+        batch.push(Record::new(&commit, parents)?);
+        progress.tick();
+        if batch.len() < COMMIT_BATCH {
+            continue;
+        }
+        // keep one batch in flight while reading the next
+        if let Some(p) = pending.take() {
+            stats += p.wait()?;
+        }
+        let batch = mem::take(&mut batch);
+        pending = Some(writer.send(move |conn| {
+            let tx = conn.transaction()?;
+            let stats = insert_commits(&tx, &batch, repo_id)?;
+            tx.commit()?;
+            Ok(stats)
+        }));
+    }
+    if let Some(p) = pending {
+        stats += p.wait()?;
+    }
+    stats += writer.call(move |conn| {
+        let tx = conn.transaction()?;
+        let stats = insert_commits(&tx, &batch, repo_id)?;
+        database::reftip::replace(&tx, &tips, repo_id)?;
+        tx.commit()?;
+        Ok(stats)
+    })?;
+    progress.finish();
+    Ok((stats, rewritten))
+}
+
+fn insert_commits(tx: &Transaction, batch: &[Record], repo_id: i64) -> Result<Stats> {
+    let mut stats = Stats::default();
+    for record in batch {
+        // interrupted runs leave stored commits without recorded tips
+        if database::commit::get_id(tx, &record.hash, repo_id)?.is_some() {
+            continue;
+        }
+        let id = database::commit::insert(tx, record, repo_id)?;
+        for (parent_hash, files) in &record.parents {
+            let Some(parent_id) = database::commit::get_id(tx, parent_hash, repo_id)? else {
+                // shallow or grafted history
+                continue;
+            };
+            let diff_id = database::relation::insert(tx, parent_id, id, repo_id)?;
+            database::change::insert(tx, diff_id, files, repo_id)?;
+            stats.changes += files.len() as u64;
         }
+        stats.commits += 1;
+    }
+    Ok(stats)
+}
+
+fn update_activity(writer: &Writer, repo_id: i64) -> Result<()> {
+    writer.call(move |conn| {
+        let tx = conn.transaction()?;
+        database::activity::refresh(&tx, repo_id)?;
+        tx.commit()?;
+        Ok(())
+    })
+}
+
+/// File of the head tip to index, `old` is the stale row of the same path
+struct CodeFile {
+    path: String,
+    blob: String,
+    old: Option<i64>,
+    content: Option<String>,
+}
+
+/// Index the files of the head branch, `HEAD` if detached
+fn update_code(writer: &Writer, repo: &Repository, repo_id: i64, head: &str) -> Result<u32> {
+    let mut files: Vec<(String, Oid)> = Vec::new();
+    let head = match head {
+        "HEAD" => repo.head(),
+        branch => repo.find_reference(&format!("refs/heads/{}", branch)),
+    };
+    // empty repository has nothing to index
+    let tree = match head {
+        Ok(head) => Some(head.peel_to_tree()?),
+        Err(e) if matches!(e.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+    if let Some(tree) = tree {
+        tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() == Some(ObjectType::Blob) {
+                if let Some(name) = entry.name() {
+                    files.push((format!("{dir}{name}"), entry.id()));
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
     }
 
+    let odb = repo.odb()?;
+    let mut old = writer.call(move |conn| database::code::get_blobs(conn, repo_id))?;
+    let mut count = 0;
+    let mut batch = Vec::with_capacity(CODE_BATCH);
+    for (path, oid) in files {
+        let hash = oid.to_string();
+        let old = match old.remove(&path) {
+            Some((_, blob)) if blob == hash => continue,
+            Some((id, _)) => Some(id),
+            None => None,
+        };
+        // read header first, never load large blobs
+        let (size, _) = odb.read_header(oid)?;
+        let blob = if size > CODE_SIZE_LIMIT {
+            None
+        } else {
+            Some(repo.find_blob(oid)?)
+        };
+        let content = blob
+            .as_ref()
+            .filter(|b| !b.is_binary())
+            .and_then(|b| str::from_utf8(b.content()).ok())
+            .map(str::to_owned);
+        batch.push(CodeFile {
+            path,
+            blob: hash,
+            old,
+            content,
+        });
+        count += 1;
+        if batch.len() == CODE_BATCH {
+            let batch = mem::take(&mut batch);
+            writer.call(move |conn| insert_code(conn, &batch, &[], repo_id))?;
+        }
+    }
+    let removed: Vec<i64> = old.into_values().map(|(id, _)| id).collect();
+    writer.call(move |conn| insert_code(conn, &batch, &removed, repo_id))?;
     Ok(count)
 }
 
-fn update_commit_one(
+fn insert_code(
     conn: &mut Connection,
-    obj: &Commit,
+    batch: &[CodeFile],
+    removed: &[i64],
     repo_id: i64,
-    child_id: Option<i64>,
-    repo: &Repository,
-) -> Result<Option<i64>> {
+) -> Result<()> {
     let tx = conn.transaction()?;
-    let hash = obj.id().to_string();
-
-    match child_id {
-        Some(child_id) => {
-            let exist_id = database::commit::get_id(&tx, &hash, repo_id)?;
-            match exist_id {
-                Some(id) => {
-                    let diff_id = database::relation::get_id(&tx, id, child_id, repo_id)?;
-                    match diff_id {
-                        Some(_) => {
-                            // stop, commit already have this child
-                            return Ok(None);
-                        }
-                        None => {
-                            // new child
-                            // insert relations
-                            let diff_id = database::relation::insert(&tx, id, child_id, repo_id)?;
-                            // insert changes
-                            update_change(&tx, obj, child_id, diff_id, repo_id, repo)?;
-                            tx.commit()?;
-                            // still stop, we have fixed relationship
-                            return Ok(None);
-                        }
-                    }
-                }
-                None => {
-                    // insert new commit
-                    let id = database::commit::insert(&tx, obj, repo_id)?;
-                    // insert relations
-                    let diff_id = database::relation::insert(&tx, id, child_id, repo_id)?;
-                    // insert changes
-                    update_change(&tx, obj, child_id, diff_id, repo_id, repo)?;
-                    tx.commit()?;
-                    return Ok(Some(id));
-                }
-            }
+    for file in batch {
+        if let Some(id) = file.old {
+            database::code::delete(&tx, id)?;
         }
-        None => {
-            let exist_id = database::commit::get_id(&tx, &hash, repo_id)?;
-            match exist_id {
-                Some(_) => {
-                    // stop, commit is exists and has no child
-                    return Ok(None);
-                }
-                None => {
-                    // insert new commit
-                    let id = database::commit::insert(&tx, obj, repo_id)?;
-                    tx.commit()?;
-                    return Ok(Some(id));
-                }
+        let content = file.content.as_deref();
+        database::code::insert(&tx, repo_id, &file.path, &file.blob, content)?;
+    }
+    for &id in removed {
+        database::code::delete(&tx, id)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn update_branch(writer: &Writer, repo: &Repository, repo_id: i64) -> Result<u64> {
+    let mut branches = Vec::new();
+    for (branch, _) in repo.branches(Some(BranchType::Local))?.flatten() {
+        let Some(name) = branch.name()? else {
+            // name is not valid utf-8
+            continue;
+        };
+        let Ok(commit) = branch.get().peel_to_commit() else {
+            continue;
+        };
+        branches.push((name.to_owned(), commit.id().to_string()));
+    }
+
+    writer.call(move |conn| {
+        let tx = conn.transaction()?;
+        let mut count = 0;
+        for (name, hash) in branches {
+            let Some(commit_id) = database::commit::get_id(&tx, &hash, repo_id)? else {
+                continue;
+            };
+            if database::branch::upsert(&tx, &name, commit_id, repo_id)? {
+                count += 1;
             }
         }
-    }
+        tx.commit()?;
+        Ok(count)
+    })
 }
 
-fn update_change(
-    tx: &Transaction,
-    obj: &Commit,
-    child_id: i64,
-    relation_id: i64,
-    repo_id: i64,
-    repo: &Repository,
-) -> Result<i64> {
-    // get tree obj from commit obj
-    let old_tree = obj.tree()?;
-    // get tree_id from child_id and tree obj from repo
-    let new_tree_id = database::commit::get_tree_by_id(tx, child_id, repo_id)?;
-    let new_tree = match new_tree_id {
-        Some(hash) => {
-            let tree = repo.find_tree(Oid::from_str(&hash)?)?;
-            Some(tree)
-        }
-        None => None,
-    };
-    // get diff from repo
-    let mut options = DiffOptions::new();
-    options.skip_binary_check(true).force_binary(true);
-    let diff = repo.diff_tree_to_tree(Some(&old_tree), new_tree.as_ref(), Some(&mut options))?;
-    // insert changs
-    database::change::insert(tx, relation_id, &diff)?;
-    Ok(0)
-}
-
-// pub fn update_branch(conn: &mut Connection, repo: &Repository) -> Result<()> {
-//     let mut tx = conn.transaction()?;
-
-//     let branches = repo.branches(Some(BranchType::Local))?;
-//     for (branch, _) in branches.flatten() {
-//         let Some(name) = branch.name()? else {
-//             // warn: name is not valid utf-8
-//             continue;
-//         };
-
-//         let reference = branch.get();
-//         let Some(commit) = reference.resolve()?.target() else {
-//             // error: branch target is None?
-//             continue;
-//         };
-//         let old_commit_option = database::branch::commit(&mut tx, name)?;
-//         if let Some(old_commit) = old_commit_option {
-//             // branch is new, skip
-//             if old_commit == commit.to_string() {
-//                 continue;
-//             }
-//             // update with new commit
-//             database::branch::upsert(&mut tx, name, &commit)?;
-//         } else {
-//             // insert new branch
-//             database::branch::upsert(&mut tx, name, &commit)?;
-//         };
-//     }
-//     // delete temp_branch that on longer exists
-//     database::branch::clear(&mut tx)?;
-//     tx.commit()?;
-//     Ok(())
-// }
-
-// pub fn update_tag(conn: &mut Connection, repo: &Repository) -> Result<()> {
-//     let mut tags: Vec<(Oid, String)> = Vec::new();
-//     repo.tag_foreach(|tag_id, name_u8| {
-//         let Ok(full_name) = str::from_utf8(name_u8) else {
-//             return true;
-//         };
-//         let short_name = full_name.strip_prefix("refs/tags/").unwrap_or(full_name);
-//         tags.push((tag_id, short_name.to_string()));
-//         true
-//     })?;
-//     let mut tx = conn.transaction()?;
-//     for (oid, name) in tags {
-//         let old_tag_target_option = database::tag::target(&mut tx, &name)?;
-//         let target_obj = repo.find_object(oid, None)?;
-//         if let Some(old_tag_target) = old_tag_target_option {
-//             if old_tag_target == oid.to_string() {
-//                 continue;
-//             }
-//             database::tag::upsert(&mut tx, &name, &target_obj)?;
-//         } else {
-//             database::tag::upsert(&mut tx, &name, &target_obj)?;
-//         }
-//     }
-//     database::tag::clear(&mut tx)?;
-//     tx.commit()?;
-//     Ok(())
-// }
+fn update_tag(writer: &Writer, repo: &Repository, repo_id: i64) -> Result<u64> {
+    let mut oids: Vec<(Oid, String)> = Vec::new();
+    repo.tag_foreach(|tag_id, name_u8| {
+        let Ok(full_name) = str::from_utf8(name_u8) else {
+            return true;
+        };
+        let short_name = full_name.strip_prefix("refs/tags/").unwrap_or(full_name);
+        oids.push((tag_id, short_name.to_string()));
+        true
+    })?;
+
+    let mut tags = Vec::new();
+    for (oid, name) in oids {
+        let obj = repo.find_object(oid, None)?;
+        // only tags on commit object
+        let Ok(commit) = obj.peel_to_commit() else {
+            continue;
+        };
+        let annotation = match obj.as_tag().and_then(|t| t.tagger()) {
+            Some(sig) => {
+                let message = obj.as_tag().and_then(|t| t.message()).unwrap_or_default();
+                Some((message.to_owned(), Person::new(&sig)?))
+            }
+            None => None,
+        };
+        tags.push((name, commit.id().to_string(), annotation));
+    }
+
+    writer.call(move |conn| {
+        let tx = conn.transaction()?;
+        let mut count = 0;
+        for (name, hash, annotation) in tags {
+            let Some(commit_id) = database::commit::get_id(&tx, &hash, repo_id)? else {
+                continue;
+            };
+            if database::tag::get_commit(&tx, &name, repo_id)? == Some(commit_id) {
+                continue;
+            }
+            let annotation = annotation.as_ref().map(|(msg, p)| (msg.as_str(), p));
+            database::tag::upsert(&tx, &name, commit_id, annotation, repo_id)?;
+            count += 1;
+        }
+        tx.commit()?;
+        Ok(count)
+    })
+}
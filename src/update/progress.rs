@@ -0,0 +1,49 @@
+use std::time::{Duration, Instant};
+
+/// Least time between two progress lines of a repository
+const INTERVAL: Duration = Duration::from_secs(2);
+
+/// Commits processed of one repository, printed with rate and ETA
+pub struct Progress<'a> {
+    name: &'a str,
+    total: usize,
+    done: usize,
+    start: Instant,
+    last: Instant,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(name: &'a str, total: usize) -> Self {
+        let now = Instant::now();
+        Progress {
+            name,
+            total,
+            done: 0,
+            start: now,
+            last: now,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.done += 1;
+        if self.last.elapsed() < INTERVAL {
+            return;
+        }
+        self.last = Instant::now();
+        let rate = self.done as f64 / self.start.elapsed().as_secs_f64();
+        let eta = (self.total - self.done) as f64 / rate;
+        println!(
+            "[update] {}: {}/{} commits, {:.0}/s, eta {:.0}s",
+            self.name, self.done, self.total, rate, eta
+        );
+    }
+
+    pub fn finish(&self) {
+        println!(
+            "[update] {}: {} commits in {:.1}s",
+            self.name,
+            self.done,
+            self.start.elapsed().as_secs_f64()
+        );
+    }
+}
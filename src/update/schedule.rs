@@ -0,0 +1,118 @@
+use super::{update, watch::Watch};
+use crate::config::{Config, Repo};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Instant;
+
+/// Repository list shared with the server, reloaded before each update
+pub type Repos = Arc<RwLock<Vec<Repo>>>;
+
+/// Handle to the background update of server mode
+#[derive(Clone)]
+pub struct Scheduler(Sender<Option<String>>);
+
+impl Scheduler {
+    /// Every repository with an interval is updated once at start
+    pub fn spawn(c: &Config, repos: Repos) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let scheduler = Scheduler(tx);
+        let watch = if c.watch {
+            Watch::new(repos.clone(), scheduler.clone())
+                .inspect_err(|e| eprintln!("[watch] disabled: {:#}", e))
+                .ok()
+        } else {
+            None
+        };
+        if watch.is_some() {
+            // catch up with changes made while not watching
+            scheduler.request(None);
+        }
+        let c = c.clone();
+        thread::spawn(move || schedule(c, repos, rx, watch));
+        scheduler
+    }
+
+    /// Update the repository named `repo` soon, or all of them if `None`.
+    /// Requests arriving during an update are merged into the next one.
+    pub fn request(&self, repo: Option<String>) {
+        // the thread only stops with the process
+        let _ = self.0.send(repo);
+    }
+}
+
+/// Next time `r` is due, `now` if never updated, `None` if it is only
+/// updated on demand
+fn due_at(c: &Config, r: &Repo, last: &HashMap<String, Instant>, now: Instant) -> Option<Instant> {
+    let interval = r.interval.or(c.interval).filter(|i| !i.is_zero())?;
+    match last.get(&r.name) {
+        Some(t) => Some(*t + interval),
+        None => Some(now),
+    }
+}
+
+fn schedule(
+    mut c: Config,
+    repos: Repos,
+    requests: Receiver<Option<String>>,
+    mut watch: Option<Watch>,
+) {
+    let mut last: HashMap<String, Instant> = HashMap::new();
+    let mut wanted: HashSet<String> = HashSet::new();
+    let mut all = false;
+    loop {
+        c.scan();
+        *repos.write().unwrap() = c.repo.clone();
+        if let Some(watch) = &mut watch {
+            watch.sync(&c.repo);
+        }
+
+        let start = Instant::now();
+        let due: Vec<&Repo> = c
+            .repo
+            .iter()
+            .filter(|r| {
+                all || wanted.contains(&r.name)
+                    || due_at(&c, r, &last, start).is_some_and(|t| t <= start)
+            })
+            .collect();
+        if !due.is_empty() {
+            let result = c
+                .open_db_rw()
+                .and_then(|mut conn| update(&c, &mut conn, &due));
+            if let Err(e) = result {
+                eprintln!("[schedule] {:#}", e);
+            }
+            for r in due {
+                last.insert(r.name.clone(), start);
+            }
+        }
+        all = false;
+        wanted.clear();
+
+        let now = Instant::now();
+        let next = c
+            .repo
+            .iter()
+            .filter_map(|r| due_at(&c, r, &last, now))
+            .min();
+        let request = match next {
+            Some(t) => requests.recv_timeout(t.saturating_duration_since(now)),
+            None => requests.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let first = match request {
+            Ok(repo) => repo,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        for repo in [first].into_iter().chain(requests.try_iter()) {
+            match repo {
+                Some(name) => {
+                    wanted.insert(name);
+                }
+                None => all = true,
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use super::schedule::{Repos, Scheduler};
+use crate::config::Repo;
+use anyhow::Result;
+use notify_debouncer_mini::{
+    new_debouncer,
+    notify::{RecommendedWatcher, RecursiveMode},
+    DebounceEventResult, Debouncer,
+};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Quiet time after the last change, a fetch writes many refs
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Inotify watches on `HEAD`, `packed-refs` and `refs/` of every repository,
+/// a change requests the update of its repository
+pub struct Watch {
+    debouncer: Debouncer<RecommendedWatcher>,
+    watched: HashSet<PathBuf>,
+}
+
+impl Watch {
+    pub fn new(repos: Repos, scheduler: Scheduler) -> Result<Self> {
+        let debouncer = new_debouncer(DEBOUNCE, move |res: DebounceEventResult| {
+            let events = match res {
+                Ok(events) => events,
+                Err(e) => {
+                    eprintln!("[watch] error: {}", e);
+                    return;
+                }
+            };
+            let mut names = HashSet::new();
+            for r in repos.read().unwrap().iter() {
+                if events.iter().any(|e| is_ref(&r.path, &e.path)) {
+                    names.insert(r.name.clone());
+                }
+            }
+            for name in names {
+                scheduler.request(Some(name));
+            }
+        })?;
+        Ok(Watch {
+            debouncer,
+            watched: HashSet::new(),
+        })
+    }
+
+    /// Follow the reloaded repository list
+    pub fn sync(&mut self, repos: &[Repo]) {
+        let paths: HashSet<PathBuf> = repos.iter().map(|r| r.path.clone()).collect();
+        let watcher = self.debouncer.watcher();
+        for path in self.watched.difference(&paths) {
+            // gone with the repository
+            let _ = watcher.unwatch(&path.join("refs"));
+            let _ = watcher.unwatch(path);
+        }
+        for path in paths.difference(&self.watched) {
+            // root is not recursive, objects/ changes all the time
+            let result = watcher
+                .watch(path, RecursiveMode::NonRecursive)
+                .and_then(|_| watcher.watch(&path.join("refs"), RecursiveMode::Recursive));
+            if let Err(e) = result {
+                eprintln!("[watch] skip: {}: {}", path.display(), e);
+            }
+        }
+        self.watched = paths;
+    }
+}
+
+/// `path` is a ref of the repository at `repo`, lock files are skipped
+/// since git renames them over the ref when done
+fn is_ref(repo: &Path, path: &Path) -> bool {
+    let Ok(rel) = path.strip_prefix(repo) else {
+        return false;
+    };
+    if path.extension().is_some_and(|e| e == "lock") {
+        return false;
+    }
+    rel == Path::new("HEAD") || rel == Path::new("packed-refs") || rel.starts_with("refs")
+}
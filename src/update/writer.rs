@@ -0,0 +1,59 @@
+use anyhow::{anyhow, Result};
+use rusqlite::Connection;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+/// Jobs waiting for the writer before workers block
+const QUEUE_SIZE: usize = 16;
+
+pub type Job = Box<dyn FnOnce(&mut Connection) + Send>;
+
+/// Handle to the only thread writing SQLite, shared by update workers
+#[derive(Clone)]
+pub struct Writer(SyncSender<Job>);
+
+/// Result of a queued job
+pub struct Pending<T>(Receiver<Result<T>>);
+
+impl Writer {
+    pub fn new() -> (Self, Receiver<Job>) {
+        let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+        (Writer(tx), rx)
+    }
+
+    /// Queue `f` without waiting, so the worker keeps reading git
+    pub fn send<T, F>(&self, f: F) -> Pending<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+        // a dropped job is reported by `Pending::wait`
+        let _ = self.0.send(Box::new(move |conn| {
+            let _ = tx.send(f(conn));
+        }));
+        Pending(rx)
+    }
+
+    pub fn call<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        self.send(f).wait()
+    }
+}
+
+impl<T> Pending<T> {
+    pub fn wait(self) -> Result<T> {
+        self.0
+            .recv()
+            .map_err(|_| anyhow!("database writer stopped"))?
+    }
+}
+
+/// Run jobs until every `Writer` is dropped
+pub fn serve(conn: &mut Connection, jobs: Receiver<Job>) {
+    for job in jobs {
+        job(conn);
+    }
+}